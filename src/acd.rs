@@ -0,0 +1,256 @@
+//! Address conflict detection, as in RFC 5227: probing for an address
+//! before using it, then announcing it with gratuitous ARP.
+
+use crate::{
+    arp,
+    error,
+    ethernet,
+    expectation::{
+        self,
+        Expectation,
+        RecvError,
+    },
+    ipv4,
+    Interface,
+};
+use rand::Rng;
+use std::{
+    thread,
+    time,
+};
+
+/// Why an address can't be used. In JSON, an object with
+/// the "kind" of conflict ("in_use" or "probing") and the "mac".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", content = "mac", rename_all = "snake_case"))]
+pub enum Conflict {
+    /// A station with this MAC claims the address.
+    InUse(ethernet::Addr),
+    /// A station with this MAC is probing for the address too.
+    Probing(ethernet::Addr),
+}
+
+/// Probes for and announces a single address. Build one with
+/// `AddressProbe::builder`. The defaults are the timings of RFC 5227.
+pub struct AddressProbe {
+    iface: Interface,
+    addr: ipv4::Addr,
+    probe_wait: time::Duration,
+    probes: usize,
+    probe_min: time::Duration,
+    probe_max: time::Duration,
+    announce_wait: time::Duration,
+    announcements: usize,
+    announce_interval: time::Duration,
+}
+
+pub struct AddressProbeBuilder {
+    iface: Option<Interface>,
+    addr: ipv4::Addr,
+    probe_wait: time::Duration,
+    probes: usize,
+    probe_min: time::Duration,
+    probe_max: time::Duration,
+    announce_wait: time::Duration,
+    announcements: usize,
+    announce_interval: time::Duration,
+}
+
+impl AddressProbe {
+    pub fn builder(addr: ipv4::Addr) -> AddressProbeBuilder {
+        AddressProbeBuilder {
+            iface: None,
+            addr,
+            probe_wait: time::Duration::from_secs(1),
+            probes: 3,
+            probe_min: time::Duration::from_secs(1),
+            probe_max: time::Duration::from_secs(2),
+            announce_wait: time::Duration::from_secs(2),
+            announcements: 2,
+            announce_interval: time::Duration::from_secs(2),
+        }
+    }
+
+    pub fn addr(&self) -> ipv4::Addr {
+        self.addr
+    }
+
+    /// Sends probes for the address, returning the first conflict
+    /// seen while doing so, or `None` if the address is free.
+    pub fn probe(&self) -> Result<Option<Conflict>, error::Error> {
+        let phy_address = self.iface.phy_address().ok_or(error::Error::NotEthernet)?;
+        let addr = self.addr;
+        let conflicts = self.iface.expect_arp(expectation::Options::forever(), move |packet| {
+            if packet.sender_hw_addr == phy_address {
+                // our own probes, if the link hands them back
+                None
+            } else if packet.sender_ip_addr == addr {
+                Some(Conflict::InUse(packet.sender_hw_addr))
+            } else if packet.operation == arp::Operation::Request
+                && packet.sender_ip_addr == ipv4::Addr::zero()
+                && packet.target_ip_addr == addr
+            {
+                Some(Conflict::Probing(packet.sender_hw_addr))
+            } else {
+                None
+            }
+        })?;
+
+        let mut rng = rand::thread_rng();
+        // so that hosts powered on together don't probe in lockstep
+        let delay = rng.gen_range(time::Duration::ZERO..=self.probe_wait);
+        if let Some(conflict) = listen(&conflicts, delay)? {
+            return Ok(Some(conflict));
+        }
+
+        for i in 0..self.probes {
+            self.iface.send_arp(arp::Packet::probe(phy_address, addr), ethernet::Addr::broadcast())?;
+
+            let wait = if i + 1 == self.probes {
+                self.announce_wait
+            } else {
+                rng.gen_range(self.probe_min..=self.probe_max)
+            };
+            if let Some(conflict) = listen(&conflicts, wait)? {
+                return Ok(Some(conflict));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Claims the address with gratuitous ARP, so that neighbours
+    /// update their caches. Doesn't check for conflicts first.
+    pub fn announce(&self) -> Result<(), error::Error> {
+        let phy_address = self.iface.phy_address().ok_or(error::Error::NotEthernet)?;
+        for i in 0..self.announcements {
+            if i > 0 {
+                thread::sleep(self.announce_interval);
+            }
+            let announcement = arp::Packet::announcement(phy_address, self.addr);
+            self.iface.send_arp(announcement, ethernet::Addr::broadcast())?;
+        }
+        Ok(())
+    }
+}
+
+/// Waits up to `timeout` for a conflict.
+fn listen(conflicts: &Expectation<Conflict>, timeout: time::Duration) -> Result<Option<Conflict>, error::Error> {
+    match conflicts.recv_timeout(timeout) {
+        Ok(conflict) => Ok(Some(conflict)),
+        Err(RecvError::Timeout) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl AddressProbeBuilder {
+    /// Probes through `iface` rather than one opened by `build`.
+    pub fn interface(self, iface: Interface) -> Self {
+        Self {
+            iface: Some(iface),
+            ..self
+        }
+    }
+
+    /// The longest random delay before the first probe.
+    pub fn probe_wait(self, probe_wait: time::Duration) -> Self {
+        Self { probe_wait, ..self }
+    }
+
+    pub fn probes(self, probes: usize) -> Self {
+        Self { probes, ..self }
+    }
+
+    /// Probes are sent a random time apart, between `min` and `max`.
+    pub fn probe_interval(self, min: time::Duration, max: time::Duration) -> Self {
+        Self {
+            probe_min: min,
+            probe_max: max.max(min),
+            ..self
+        }
+    }
+
+    /// How long to wait for conflicts after the last probe.
+    pub fn announce_wait(self, announce_wait: time::Duration) -> Self {
+        Self { announce_wait, ..self }
+    }
+
+    pub fn announcements(self, announcements: usize) -> Self {
+        Self { announcements, ..self }
+    }
+
+    pub fn announce_interval(self, announce_interval: time::Duration) -> Self {
+        Self {
+            announce_interval,
+            ..self
+        }
+    }
+
+    /// Opens the default interface, unless one was given.
+    pub fn build(self) -> Result<AddressProbe, error::Error> {
+        let iface = match self.iface {
+            Some(iface) => iface,
+            None => Interface::open_default()?,
+        };
+
+        Ok(AddressProbe {
+            iface,
+            addr: self.addr,
+            probe_wait: self.probe_wait,
+            probes: self.probes,
+            probe_min: self.probe_min,
+            probe_max: self.probe_max,
+            announce_wait: self.announce_wait,
+            announcements: self.announcements,
+            announce_interval: self.announce_interval,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        expectation::Options,
+        sim::{
+            fixture::*,
+            Conditions,
+            Host,
+            Network,
+        },
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn address_probe_finds_conflicts_and_announces() {
+        let net = Network::new(0);
+        let lan = net.segment(Conditions::default());
+        net.host(lan, Host::new(mac(1), ip("10.0.0.1")));
+        net.host(lan, Host::new(mac(3), ip("10.0.0.3")));
+        let iface = station(&net, lan, 2);
+        let neighbour = station(&net, lan, 7);
+
+        let probe = |addr| {
+            AddressProbe::builder(ip(addr))
+                .interface(iface.clone())
+                .probe_wait(Duration::ZERO)
+                .probe_interval(Duration::from_millis(10), Duration::from_millis(20))
+                .announce_wait(Duration::from_millis(100))
+                .announce_interval(Duration::from_millis(10))
+                .build()
+                .unwrap()
+        };
+        assert_eq!(probe("10.0.0.3").probe().unwrap(), Some(Conflict::InUse(mac(3))));
+        assert_eq!(probe("10.0.0.9").probe().unwrap(), None);
+
+        let announcements = neighbour
+            .expect_arp(Options::times(2).timeout(TIMEOUT), |packet| {
+                Some((packet.sender_hw_addr, packet.sender_ip_addr, packet.target_ip_addr))
+            })
+            .unwrap();
+        probe("10.0.0.9").announce().unwrap();
+        for _ in 0..2 {
+            assert_eq!(announcements.recv().unwrap(), (mac(2), ip("10.0.0.9"), ip("10.0.0.9")));
+        }
+    }
+}
@@ -2,7 +2,6 @@ use crate::{
     ethernet,
     ipv4,
     parse,
-    netinfo,
 };
 use derive_try_from_primitive::*;
 use nom::{
@@ -14,7 +13,8 @@ use nom::{
 use cookie_factory as cf;
 use std::io;
 
-#[derive(Debug, TryFromPrimitive, Clone, Copy)]
+#[derive(Debug, TryFromPrimitive, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u16)]
 pub enum Operation {
     Request = 1,
@@ -27,7 +27,8 @@ pub enum HardwareType {
     Ethernet = 1,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Packet {
     pub operation: Operation,
     pub sender_hw_addr: ethernet::Addr,
@@ -37,15 +38,27 @@ pub struct Packet {
 }
 
 impl Packet {
-    pub fn request(nic: &netinfo::NIC) -> Self {
+    /// Asks who has `target`, on behalf of `sender_hw_addr` and `sender_ip_addr`.
+    pub fn request(sender_hw_addr: ethernet::Addr, sender_ip_addr: ipv4::Addr, target: ipv4::Addr) -> Self {
         Self {
             operation: Operation::Request,
-            sender_hw_addr: nic.phy_address,
-            sender_ip_addr: nic.address,
+            sender_hw_addr,
+            sender_ip_addr,
             target_hw_addr: ethernet::Addr::zero(),
-            target_ip_addr: nic.gateway,
+            target_ip_addr: target,
         }
     }
+
+    /// An RFC 5227 probe, asking whether anyone has `target`
+    /// without claiming any address ourselves.
+    pub fn probe(sender_hw_addr: ethernet::Addr, target: ipv4::Addr) -> Self {
+        Self::request(sender_hw_addr, ipv4::Addr::zero(), target)
+    }
+
+    /// A gratuitous ARP request, telling everyone `addr` is ours.
+    pub fn announcement(sender_hw_addr: ethernet::Addr, addr: ipv4::Addr) -> Self {
+        Self::request(sender_hw_addr, addr, addr)
+    }
 }
 
 impl Operation {
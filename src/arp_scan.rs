@@ -0,0 +1,190 @@
+use crate::{
+    arp,
+    error,
+    ethernet,
+    expectation,
+    ipv4,
+    Interface,
+};
+use std::{
+    collections::BTreeSet,
+    thread,
+    time,
+};
+
+/// An address and the MAC that claimed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Binding {
+    pub ip: ipv4::Addr,
+    pub mac: ethernet::Addr,
+}
+
+/// Broadcasts an ARP request for every host of a block, at a steady
+/// rate, and collects the replies. Build one with `ArpScan::builder`.
+pub struct ArpScan {
+    iface: Interface,
+    cidr: ipv4::Cidr,
+    rate: u32,
+    retries: usize,
+    timeout: time::Duration,
+}
+
+pub struct ArpScanBuilder {
+    iface: Option<Interface>,
+    cidr: ipv4::Cidr,
+    rate: u32,
+    retries: usize,
+    timeout: time::Duration,
+}
+
+impl ArpScan {
+    pub fn builder(cidr: ipv4::Cidr) -> ArpScanBuilder {
+        ArpScanBuilder {
+            iface: None,
+            cidr,
+            rate: 100,
+            retries: 0,
+            timeout: time::Duration::from_secs(1),
+        }
+    }
+
+    pub fn cidr(&self) -> ipv4::Cidr {
+        self.cidr
+    }
+
+    /// Scans the whole block, returning who answered sorted by address.
+    /// An address claimed by several MACs has a binding for each.
+    pub fn run(&self) -> Result<Vec<Binding>, error::Error> {
+        let phy_address = self.iface.phy_address().ok_or(error::Error::NotEthernet)?;
+        let (cidr, our_ip) = (self.cidr, self.iface.address());
+        let rx = self.iface.expect_arp(expectation::Options::forever(), move |packet| {
+            if packet.operation == arp::Operation::Reply
+                && packet.target_ip_addr == our_ip
+                && cidr.contains(packet.sender_ip_addr)
+            {
+                Some(Binding {
+                    ip: packet.sender_ip_addr,
+                    mac: packet.sender_hw_addr,
+                })
+            } else {
+                None
+            }
+        })?;
+
+        let mut bindings = BTreeSet::new();
+        let period = time::Duration::from_secs(1) / self.rate.max(1);
+        // we don't answer our own requests
+        let mut pending: Vec<_> = self.cidr.hosts().filter(|&ip| ip != our_ip).collect();
+
+        for _ in 0..=self.retries {
+            let start = time::Instant::now();
+            for (i, &ip) in pending.iter().enumerate() {
+                let request = arp::Packet::request(phy_address, our_ip, ip);
+                self.iface.send_arp(request, ethernet::Addr::broadcast())?;
+
+                // replies are picked up while waiting for the next send
+                let next = start + period * (i as u32 + 1);
+                while let Ok(binding) = rx.recv_timeout(next.saturating_duration_since(time::Instant::now())) {
+                    bindings.insert(binding);
+                }
+                thread::sleep(next.saturating_duration_since(time::Instant::now()));
+            }
+
+            let end = time::Instant::now() + self.timeout;
+            while let Ok(binding) = rx.recv_timeout(end.saturating_duration_since(time::Instant::now())) {
+                bindings.insert(binding);
+            }
+
+            // retries only go to those who haven't answered yet
+            pending.retain(|&ip| !bindings.iter().any(|binding| binding.ip == ip));
+            if pending.is_empty() {
+                break;
+            }
+        }
+
+        Ok(bindings.into_iter().collect())
+    }
+}
+
+impl ArpScanBuilder {
+    /// Scans through `iface` rather than one opened by `build`.
+    pub fn interface(self, iface: Interface) -> Self {
+        Self {
+            iface: Some(iface),
+            ..self
+        }
+    }
+
+    /// ARP requests sent per second.
+    pub fn rate(self, rate: u32) -> Self {
+        Self { rate, ..self }
+    }
+
+    /// How many more times to ask the hosts that didn't answer.
+    pub fn retries(self, retries: usize) -> Self {
+        Self { retries, ..self }
+    }
+
+    /// How long to wait for replies after the last request of each round.
+    pub fn timeout(self, timeout: time::Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// Opens the default interface, unless one was given.
+    pub fn build(self) -> Result<ArpScan, error::Error> {
+        let iface = match self.iface {
+            Some(iface) => iface,
+            None => Interface::open_default()?,
+        };
+
+        Ok(ArpScan {
+            iface,
+            cidr: self.cidr,
+            rate: self.rate,
+            retries: self.retries,
+            timeout: self.timeout,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sim::{
+        fixture::*,
+        Conditions,
+        Host,
+        Network,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn arp_scan_lists_bindings() {
+        let net = Network::new(0);
+        let lan = net.segment(Conditions::default());
+        net.host(lan, Host::new(mac(1), ip("10.0.0.1")));
+        net.host(lan, Host::new(mac(4), ip("10.0.0.4")));
+        net.host(lan, Host::new(mac(5), ip("10.0.0.5")));
+        net.host(lan, Host::new(mac(6), ip("10.0.0.5")));
+        let iface = station(&net, lan, 2);
+        assert_eq!(iface.subnet(), Some("10.0.0.0/24".parse().unwrap()));
+
+        let scan = ArpScan::builder("10.0.0.0/29".parse().unwrap())
+            .interface(iface)
+            .rate(1000)
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let bindings: Vec<_> = scan.run().unwrap().into_iter().map(|binding| (binding.ip, binding.mac)).collect();
+        assert_eq!(
+            bindings,
+            vec![
+                (ip("10.0.0.1"), mac(1)),
+                (ip("10.0.0.4"), mac(4)),
+                (ip("10.0.0.5"), mac(5)),
+                (ip("10.0.0.5"), mac(6)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use crate::{
+    arp,
+    error,
+    ethernet,
+    expectation::{
+        self,
+        Expectation,
+    },
+    ipv4,
+    Interface,
+};
+use std::{
+    thread,
+    time,
+};
+
+/// An ARP reply to one of our requests.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ArpReply {
+    pub sequence_number: u16,
+    pub mac: ethernet::Addr,
+    #[cfg_attr(feature = "serde", serde(rename = "rtt_ms", serialize_with = "crate::json::millis"))]
+    pub rtt: time::Duration,
+}
+
+/// Something that happened to an ARP request, tagged
+/// with a "type" field in JSON like `PingEvent`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum ArpingEvent {
+    /// The first reply to a request from that MAC. Several of them
+    /// for one request mean several stations claim the address.
+    Reply(ArpReply),
+    /// Another reply to a request, from a MAC that already answered it.
+    Duplicate(ArpReply),
+    Timeout { sequence_number: u16 },
+}
+
+/// Sends ARP requests for an on-link address, one at a time.
+/// Build one with `Arping::builder`.
+pub struct Arping {
+    iface: Interface,
+    target: ipv4::Addr,
+    count: Option<usize>,
+    interval: time::Duration,
+    timeout: time::Duration,
+}
+
+pub struct ArpingBuilder {
+    iface: Option<Interface>,
+    target: ipv4::Addr,
+    count: Option<usize>,
+    interval: time::Duration,
+    timeout: time::Duration,
+}
+
+impl Arping {
+    pub fn builder(target: ipv4::Addr) -> ArpingBuilder {
+        ArpingBuilder {
+            iface: None,
+            target,
+            count: Some(4),
+            interval: time::Duration::from_secs(1),
+            timeout: time::Duration::from_secs(1),
+        }
+    }
+
+    pub fn target(&self) -> ipv4::Addr {
+        self.target
+    }
+
+    /// Fails with `NotEthernet` if the interface can't send ARP.
+    pub fn iter(&self) -> Result<ArpPings<'_>, error::Error> {
+        if self.iface.phy_address().is_none() {
+            return Err(error::Error::NotEthernet);
+        }
+
+        let (target, our_ip) = (self.target, self.iface.address());
+        let replies = self.iface.expect_arp(expectation::Options::forever(), move |packet| {
+            if packet.operation == arp::Operation::Reply
+                && packet.sender_ip_addr == target
+                && packet.target_ip_addr == our_ip
+            {
+                Some((packet.sender_hw_addr, time::Instant::now()))
+            } else {
+                None
+            }
+        })?;
+
+        Ok(ArpPings {
+            arping: self,
+            replies,
+            sent: 0,
+            next_send: time::Instant::now(),
+            current: None,
+        })
+    }
+
+    fn send(&self) -> Result<(), error::Error> {
+        let phy_address = self.iface.phy_address().ok_or(error::Error::NotEthernet)?;
+        let request = arp::Packet::request(phy_address, self.iface.address(), self.target);
+        self.iface.send_arp(request, ethernet::Addr::broadcast())
+    }
+}
+
+impl ArpingBuilder {
+    /// Sends through `iface` rather than the default one.
+    pub fn interface(self, iface: Interface) -> Self {
+        Self {
+            iface: Some(iface),
+            ..self
+        }
+    }
+
+    pub fn count(self, count: usize) -> Self {
+        Self {
+            count: Some(count),
+            ..self
+        }
+    }
+
+    /// Keeps sending requests until the iterator is dropped.
+    pub fn continuous(self) -> Self {
+        Self { count: None, ..self }
+    }
+
+    pub fn interval(self, interval: time::Duration) -> Self {
+        Self { interval, ..self }
+    }
+
+    /// How long to wait for the first reply to each request.
+    pub fn timeout(self, timeout: time::Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// Opens the default interface, unless one was given.
+    /// It has to carry Ethernet frames, which ICMP sockets don't.
+    pub fn build(self) -> Result<Arping, error::Error> {
+        let iface = match self.iface {
+            Some(iface) => iface,
+            None => Interface::open_default()?,
+        };
+
+        Ok(Arping {
+            iface,
+            target: self.target,
+            count: self.count,
+            interval: self.interval,
+            timeout: self.timeout,
+        })
+    }
+}
+
+struct Request {
+    sequence_number: u16,
+    sent_at: time::Instant,
+    // who answered it so far
+    macs: Vec<ethernet::Addr>,
+}
+
+/// Sends requests as it's iterated, yielding what happens to them.
+/// ARP replies don't say which request they answer, so every reply
+/// until the next request goes to the last one sent.
+pub struct ArpPings<'a> {
+    arping: &'a Arping,
+    replies: Expectation<(ethernet::Addr, time::Instant)>,
+    sent: usize,
+    next_send: time::Instant,
+    current: Option<Request>,
+}
+
+impl Iterator for ArpPings<'_> {
+    type Item = Result<ArpingEvent, error::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let request = match self.current {
+                Some(ref mut request) => request,
+                None => {
+                    if matches!(self.arping.count, Some(count) if self.sent >= count) {
+                        return None;
+                    }
+                    thread::sleep(self.next_send.saturating_duration_since(time::Instant::now()));
+
+                    let sent_at = time::Instant::now();
+                    let sequence_number = self.sent as u16;
+                    self.sent += 1;
+                    // keep to the schedule, unless we've fallen behind it
+                    self.next_send = (self.next_send + self.arping.interval).max(sent_at);
+                    if let Err(e) = self.arping.send() {
+                        return Some(Err(e));
+                    }
+                    self.current.insert(Request {
+                        sequence_number,
+                        sent_at,
+                        macs: Vec::new(),
+                    })
+                }
+            };
+
+            // once answered, listen for more replies until the next request
+            let timed_out_at = request.sent_at + self.arping.timeout;
+            let last = matches!(self.arping.count, Some(count) if self.sent >= count);
+            let until = if request.macs.is_empty() || last {
+                timed_out_at
+            } else {
+                timed_out_at.max(self.next_send)
+            };
+
+            let wait = until.saturating_duration_since(time::Instant::now());
+            match self.replies.recv_timeout(wait) {
+                // left over from an earlier request
+                Ok((_, at)) if at < request.sent_at => {}
+                Ok((mac, at)) => {
+                    let reply = ArpReply {
+                        sequence_number: request.sequence_number,
+                        mac,
+                        rtt: at - request.sent_at,
+                    };
+                    if request.macs.contains(&mac) {
+                        return Some(Ok(ArpingEvent::Duplicate(reply)));
+                    }
+                    request.macs.push(mac);
+                    return Some(Ok(ArpingEvent::Reply(reply)));
+                }
+                Err(expectation::RecvError::Timeout) => {
+                    let request = self.current.take().unwrap();
+                    if request.macs.is_empty() {
+                        return Some(Ok(ArpingEvent::Timeout {
+                            sequence_number: request.sequence_number,
+                        }));
+                    }
+                }
+                // the interface is gone
+                Err(expectation::RecvError::Finished) => {
+                    self.current = None;
+                    return None;
+                }
+                Err(e) => {
+                    self.current = None;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sim::{
+        fixture::*,
+        Conditions,
+        Host,
+        Network,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn arping_reports_mac_and_timeouts() {
+        let net = Network::new(0);
+        let lan = net.segment(Conditions::default());
+        net.host(lan, Host::new(mac(1), ip("10.0.0.1")));
+        net.host(lan, Host::new(mac(3), ip("10.0.0.3")));
+        let iface = station(&net, lan, 2);
+
+        let arping = Arping::builder(ip("10.0.0.3"))
+            .interface(iface.clone())
+            .count(2)
+            .interval(Duration::from_millis(10))
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let replies: Vec<_> = arping
+            .iter()
+            .unwrap()
+            .map(|event| match event.unwrap() {
+                ArpingEvent::Reply(reply) => (reply.sequence_number, reply.mac),
+                event => panic!("unexpected {:?}", event),
+            })
+            .collect();
+        assert_eq!(replies, vec![(0, mac(3)), (1, mac(3))]);
+
+        let arping = Arping::builder(ip("10.0.0.9"))
+            .interface(iface)
+            .count(1)
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let events: Vec<_> = arping.iter().unwrap().map(Result::unwrap).collect();
+        assert!(matches!(events[..], [ArpingEvent::Timeout { sequence_number: 0 }]));
+    }
+
+    #[test]
+    fn arping_sees_several_macs() {
+        let net = Network::new(0);
+        let lan = net.segment(Conditions::default());
+        net.host(lan, Host::new(mac(1), ip("10.0.0.1")));
+        net.host(lan, Host::new(mac(3), ip("10.0.0.3")));
+        net.host(lan, Host::new(mac(4), ip("10.0.0.3")));
+        let iface = station(&net, lan, 2);
+
+        let arping = Arping::builder(ip("10.0.0.3"))
+            .interface(iface)
+            .count(1)
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let mut macs: Vec<_> = arping
+            .iter()
+            .unwrap()
+            .map(|event| match event.unwrap() {
+                ArpingEvent::Reply(reply) => reply.mac,
+                event => panic!("unexpected {:?}", event),
+            })
+            .collect();
+        macs.sort();
+        assert_eq!(macs, vec![mac(3), mac(4)]);
+    }
+
+    #[test]
+    fn arping_ends_when_the_interface_closes() {
+        let (net, lan, iface) = lan();
+        net.host(lan, Host::new(mac(3), ip("10.0.0.3")));
+
+        let arping = Arping::builder(ip("10.0.0.3"))
+            .interface(iface.clone())
+            .continuous()
+            .interval(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let mut events = arping.iter().unwrap();
+        assert!(matches!(events.next(), Some(Ok(ArpingEvent::Reply(_)))));
+
+        iface.close().unwrap();
+        assert!(events.next().is_none());
+    }
+}
@@ -0,0 +1,372 @@
+use crate::{
+    arp,
+    arp_scan::Binding,
+    error,
+    ethernet,
+    expectation::{
+        self,
+        Expectation,
+    },
+    ipv4,
+    Interface,
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs,
+    io::{
+        self,
+        Write,
+    },
+    path::Path,
+    time,
+};
+
+/// The MAC an address is bound to, as far as we know.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub mac: ethernet::Addr,
+    /// When the address was first seen with this MAC.
+    pub since: time::SystemTime,
+    /// The MAC the address had before, if it ever changed.
+    pub previous: Option<ethernet::Addr>,
+}
+
+/// Something about a binding that deserves attention.
+/// In JSON, its `kind` is in an "alert" field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "alert", rename_all = "kebab-case"))]
+pub enum Alert {
+    NewStation(Binding),
+    /// An address moved to another MAC.
+    Changed {
+        ip: ipv4::Addr,
+        old: ethernet::Addr,
+        new: ethernet::Addr,
+    },
+    /// An address moved back to the MAC it had before,
+    /// e.g. because two stations are fighting over it.
+    FlipFlop {
+        ip: ipv4::Addr,
+        old: ethernet::Addr,
+        new: ethernet::Addr,
+    },
+    /// The gateway moved to another MAC, the usual sign of ARP spoofing.
+    GatewayChanged {
+        ip: ipv4::Addr,
+        old: ethernet::Addr,
+        new: ethernet::Addr,
+    },
+}
+
+impl Alert {
+    /// A short name for the kind of alert, e.g. for scripts.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Self::NewStation(_) => "new-station",
+            Self::Changed { .. } => "changed",
+            Self::FlipFlop { .. } => "flip-flop",
+            Self::GatewayChanged { .. } => "gateway-changed",
+        }
+    }
+
+    /// The binding that caused the alert.
+    pub fn binding(&self) -> Binding {
+        match *self {
+            Self::NewStation(binding) => binding,
+            Self::Changed { ip, new, .. } | Self::FlipFlop { ip, new, .. } | Self::GatewayChanged { ip, new, .. } => {
+                Binding { ip, mac: new }
+            }
+        }
+    }
+
+    /// The MAC the address had before, unless it's new.
+    pub fn old_mac(&self) -> Option<ethernet::Addr> {
+        match *self {
+            Self::NewStation(_) => None,
+            Self::Changed { old, .. } | Self::FlipFlop { old, .. } | Self::GatewayChanged { old, .. } => Some(old),
+        }
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::NewStation(Binding { ip, mac }) => write!(f, "new station {:?} {}", ip, mac),
+            Self::Changed { ip, old, new } => write!(f, "changed ethernet address {:?} {} -> {}", ip, old, new),
+            Self::FlipFlop { ip, old, new } => write!(f, "flip flop {:?} {} -> {}", ip, old, new),
+            Self::GatewayChanged { ip, old, new } => write!(f, "gateway changed {:?} {} -> {}", ip, old, new),
+        }
+    }
+}
+
+/// The IP to MAC bindings seen so far, which can be kept in a file
+/// between runs. Each line of the file holds an address, its MAC,
+/// when that was first seen in unix seconds, and the previous MAC if any.
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    entries: BTreeMap<ipv4::Addr, Entry>,
+}
+
+impl Database {
+    /// Reads a database saved with `save`. A missing file is an empty database.
+    pub fn load(path: &Path) -> Result<Self, error::Error> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = BTreeMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (ip, entry) = parse_entry(line)
+                .ok_or_else(|| error::Error::Parse(format!("{}:{}: {:?}", path.display(), i + 1, line)))?;
+            entries.insert(ip, entry);
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        writeln!(file, "# ip mac since [previous mac]")?;
+        for (ip, entry) in &self.entries {
+            let since = entry.since.duration_since(time::UNIX_EPOCH).unwrap_or_default();
+            write!(file, "{:?} {} {}", ip, entry.mac, since.as_secs())?;
+            if let Some(previous) = entry.previous {
+                write!(file, " {}", previous)?;
+            }
+            writeln!(file)?;
+        }
+        file.flush()
+    }
+
+    pub fn get(&self, ip: ipv4::Addr) -> Option<&Entry> {
+        self.entries.get(&ip)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ipv4::Addr, &Entry)> {
+        self.entries.iter().map(|(ip, entry)| (*ip, entry))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Records `binding`, returning what's alarming about it, if anything.
+    /// A change of `gateway`'s MAC gets its own alert.
+    pub fn observe(&mut self, binding: Binding, gateway: Option<ipv4::Addr>, now: time::SystemTime) -> Option<Alert> {
+        let Binding { ip, mac } = binding;
+        let entry = match self.entries.get_mut(&ip) {
+            Some(entry) => entry,
+            None => {
+                let entry = Entry {
+                    mac,
+                    since: now,
+                    previous: None,
+                };
+                self.entries.insert(ip, entry);
+                return Some(Alert::NewStation(binding));
+            }
+        };
+        if entry.mac == mac {
+            return None;
+        }
+
+        let old = entry.mac;
+        let alert = if Some(ip) == gateway {
+            Alert::GatewayChanged { ip, old, new: mac }
+        } else if entry.previous == Some(mac) {
+            Alert::FlipFlop { ip, old, new: mac }
+        } else {
+            Alert::Changed { ip, old, new: mac }
+        };
+        *entry = Entry {
+            mac,
+            since: now,
+            previous: Some(old),
+        };
+        Some(alert)
+    }
+}
+
+fn parse_entry(line: &str) -> Option<(ipv4::Addr, Entry)> {
+    let mut fields = line.split_whitespace();
+    let ip = fields.next()?.parse().ok()?;
+    let mac = fields.next()?.parse().ok()?;
+    let since = time::UNIX_EPOCH + time::Duration::from_secs(fields.next()?.parse().ok()?);
+    let previous = match fields.next() {
+        Some(previous) => Some(previous.parse().ok()?),
+        None => None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((ip, Entry { mac, since, previous }))
+}
+
+/// Listens to every ARP packet the interface sees, recording the
+/// sender's binding and yielding alerts about it. It ends when
+/// the interface is closed.
+pub struct ArpWatch {
+    packets: Expectation<arp::Packet>,
+    database: Database,
+    gateway: Option<ipv4::Addr>,
+}
+
+impl ArpWatch {
+    /// Starts from the bindings of `database`.
+    pub fn new(iface: &Interface, database: Database) -> Result<Self, error::Error> {
+        if iface.phy_address().is_none() {
+            return Err(error::Error::NotEthernet);
+        }
+        // replies between other stations matter most
+        let packets = iface.expect_all_arp(expectation::Options::forever(), |packet| Some(packet.clone()))?;
+
+        Ok(Self {
+            packets,
+            database,
+            gateway: iface.gateway(),
+        })
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.database
+    }
+}
+
+impl Iterator for ArpWatch {
+    type Item = Alert;
+
+    fn next(&mut self) -> Option<Alert> {
+        loop {
+            let packet = self.packets.recv().ok()?;
+            // probes don't claim anything
+            if packet.sender_ip_addr == ipv4::Addr::zero() {
+                continue;
+            }
+            let binding = Binding {
+                ip: packet.sender_ip_addr,
+                mac: packet.sender_hw_addr,
+            };
+            if let Some(alert) = self.database.observe(binding, self.gateway, time::SystemTime::now()) {
+                return Some(alert);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        link,
+        sim::{
+            fixture::*,
+            Conditions,
+            Host,
+            Network,
+        },
+    };
+    use std::sync::Arc;
+
+    fn binding(ip: &str, last: u8) -> Binding {
+        Binding {
+            ip: ip.parse().unwrap(),
+            mac: mac(last),
+        }
+    }
+
+    #[test]
+    fn database_raises_alerts() {
+        let mut db = Database::default();
+        let gateway = Some("10.0.0.1".parse().unwrap());
+        let mut observe = |ip, mac| db.observe(binding(ip, mac), gateway, time::UNIX_EPOCH);
+
+        assert_eq!(observe("10.0.0.5", 5), Some(Alert::NewStation(binding("10.0.0.5", 5))));
+        assert_eq!(observe("10.0.0.5", 5), None);
+        assert!(matches!(observe("10.0.0.5", 6), Some(Alert::Changed { .. })));
+        assert!(matches!(observe("10.0.0.5", 5), Some(Alert::FlipFlop { .. })));
+
+        assert!(matches!(observe("10.0.0.1", 1), Some(Alert::NewStation(_))));
+        let alert = observe("10.0.0.1", 66).unwrap();
+        assert_eq!(alert.kind(), "gateway-changed");
+        assert_eq!(alert.old_mac(), Some(binding("10.0.0.1", 1).mac));
+    }
+
+    #[test]
+    fn database_survives_a_round_trip() {
+        let mut db = Database::default();
+        let since = time::UNIX_EPOCH + time::Duration::from_secs(1_600_000_000);
+        db.observe(binding("10.0.0.5", 5), None, since);
+        db.observe(binding("10.0.0.5", 0xAB), None, since);
+        db.observe(binding("10.0.0.7", 7), None, since);
+
+        let path = std::env::temp_dir().join(format!("oppa-arpwatch-{}.dat", std::process::id()));
+        db.save(&path).unwrap();
+        let loaded = Database::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.iter().collect::<Vec<_>>(), db.iter().collect::<Vec<_>>());
+        assert_eq!(loaded.get("10.0.0.5".parse().unwrap()).unwrap().previous, Some(binding("10.0.0.5", 5).mac));
+    }
+
+    #[test]
+    fn arpwatch_alerts_on_changed_bindings() {
+        let (net, lan, iface) = lan();
+        let mut watch = ArpWatch::new(&iface, Database::default()).unwrap();
+
+        // two stations claiming the same address, one after the other
+        let mut alerts = Vec::new();
+        for last in [7, 8] {
+            let port = net.port(lan, mac(last), ip("10.0.0.7"));
+            let station = Interface::open_ethernet(Arc::new(port), nic("10.0.0.7", last, "10.0.0.1")).unwrap();
+            station.send_arp(arp::Packet::announcement(mac(last), ip("10.0.0.7")), ethernet::Addr::broadcast()).unwrap();
+            alerts.push(watch.next().unwrap());
+        }
+        assert_eq!(alerts[0], Alert::NewStation(Binding { ip: ip("10.0.0.7"), mac: mac(7) }));
+        assert_eq!(alerts[1], Alert::Changed { ip: ip("10.0.0.7"), old: mac(7), new: mac(8) });
+    }
+
+    #[test]
+    fn arpwatch_sees_spoofed_unicast_replies() {
+        let net = Network::new(0);
+        let lan = net.segment(Conditions::default());
+        net.host(lan, Host::new(mac(1), ip("10.0.0.1")));
+        net.host(lan, Host::new(mac(5), ip("10.0.0.5")));
+        let iface = open(net.mirror_port(lan, mac(2), ip("10.0.0.2")), 2);
+        let mut database = Database::default();
+        database.observe(Binding { ip: ip("10.0.0.1"), mac: mac(1) }, None, std::time::UNIX_EPOCH);
+        let mut watch = ArpWatch::new(&iface, database).unwrap();
+
+        // a third station tells .5, and only .5, that it is the gateway
+        let attacker = net.port(lan, mac(66), ip("10.0.0.66"));
+        let spoofed = arp::Packet {
+            operation: arp::Operation::Reply,
+            sender_hw_addr: mac(66),
+            sender_ip_addr: ip("10.0.0.1"),
+            target_hw_addr: mac(5),
+            target_ip_addr: ip("10.0.0.5"),
+        };
+        let frame = serialize(ethernet::Frame {
+            src: mac(66),
+            dst: mac(5),
+            ether_type: Some(ethernet::EtherType::ARP),
+            payload: ethernet::Payload::ARP(spoofed),
+        });
+        link::Link::send(&attacker, &frame).unwrap();
+
+        assert_eq!(
+            watch.next().unwrap(),
+            Alert::GatewayChanged { ip: ip("10.0.0.1"), old: mac(1), new: mac(66) }
+        );
+    }
+}
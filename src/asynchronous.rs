@@ -0,0 +1,233 @@
+//! An async facade over `Interface`.
+//!
+//! Nothing here needs a particular runtime: expectations are woken up by
+//! the interface's own capture and expiry threads, which also take care
+//! of timeouts.
+
+use crate::{
+    arp,
+    demux,
+    error,
+    expectation::{
+        self,
+        Expectation,
+    },
+    icmp,
+    ipv4,
+    Interface,
+};
+use std::time;
+
+/// What an echo request looks like, and how long to wait for its reply.
+#[derive(Debug, Clone)]
+pub struct PingOptions {
+    pub identifier: u16,
+    pub sequence_number: u16,
+    pub payload: Vec<u8>,
+    pub timeout: time::Duration,
+}
+
+impl Default for PingOptions {
+    fn default() -> Self {
+        Self {
+            identifier: rand::random(),
+            sequence_number: 0,
+            payload: b"oppa".to_vec(),
+            timeout: time::Duration::from_secs(3),
+        }
+    }
+}
+
+/// An echo reply to a ping.
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub src: ipv4::Addr,
+    pub ttl: u8,
+    pub size: usize,
+    pub rtt: time::Duration,
+}
+
+#[derive(Clone)]
+pub struct AsyncInterface {
+    iface: Interface,
+}
+
+impl From<Interface> for AsyncInterface {
+    fn from(iface: Interface) -> Self {
+        Self { iface }
+    }
+}
+
+impl AsyncInterface {
+    /// The blocking interface underneath, which shares everything with this one.
+    pub fn blocking(&self) -> &Interface {
+        &self.iface
+    }
+
+    /// Sending doesn't wait on the network, so this
+    /// completes as soon as the link has taken the packet.
+    pub async fn send_ipv4(&self, payload: ipv4::Payload, addr: &ipv4::Addr) -> Result<(), error::Error> {
+        self.iface.send_ipv4(payload, addr)
+    }
+
+    /// See `Interface::expect_ipv4`, and what `f` mustn't do there;
+    /// the other `expect_` functions are the same.
+    pub fn expect_ipv4<F, T>(
+        &self,
+        protocol: ipv4::Protocol,
+        options: expectation::Options,
+        f: F,
+    ) -> Result<Expectation<T>, error::Error>
+    where
+        F: Fn(&ipv4::Packet) -> Option<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.iface.expect_ipv4(protocol, options, f)
+    }
+
+    pub fn expect_keyed<F, T>(
+        &self,
+        key: demux::Key,
+        options: expectation::Options,
+        f: F,
+    ) -> Result<Expectation<T>, error::Error>
+    where
+        F: Fn(&ipv4::Packet) -> Option<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.iface.expect_keyed(key, options, f)
+    }
+
+    pub fn expect_arp<F, T>(&self, options: expectation::Options, f: F) -> Result<Expectation<T>, error::Error>
+    where
+        F: Fn(&arp::Packet) -> Option<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.iface.expect_arp(options, f)
+    }
+
+    pub fn expect_all_arp<F, T>(&self, options: expectation::Options, f: F) -> Result<Expectation<T>, error::Error>
+    where
+        F: Fn(&arp::Packet) -> Option<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.iface.expect_all_arp(options, f)
+    }
+
+    pub fn expect_frame<F, T>(&self, options: expectation::Options, f: F) -> Result<Expectation<T>, error::Error>
+    where
+        F: Fn(&[u8]) -> Option<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.iface.expect_frame(options, f)
+    }
+
+    /// Sends a single echo request to `dest` and waits for the reply.
+    /// An ICMP error about the request fails with `Error::Icmp`.
+    pub async fn ping(&self, dest: ipv4::Addr, opts: &PingOptions) -> Result<Reply, error::Error> {
+        let key = demux::Key::Echo {
+            peer: dest,
+            identifier: opts.identifier,
+            sequence_number: opts.sequence_number,
+        };
+
+        let before = time::Instant::now();
+        let mut rx = self.expect_keyed(key, expectation::Options::once().timeout(opts.timeout), move |packet| {
+            match packet.payload {
+                ipv4::Payload::ICMP(icmp::Packet { header: icmp::Header::EchoReply(_), ref payload, .. }) => {
+                    Some(Ok(Reply {
+                        src: packet.src,
+                        ttl: packet.ttl,
+                        size: payload.0.len(),
+                        rtt: before.elapsed(),
+                    }))
+                }
+                // the demux only hands us errors quoting our request
+                ipv4::Payload::ICMP(ref icmp_packet) => Some(Err(error::Error::Icmp {
+                    from: packet.src,
+                    typ: icmp_packet.typ.clone(),
+                })),
+                _ => None,
+            }
+        })?;
+
+        let echo = icmp::Echo {
+            identifier: opts.identifier,
+            sequence_number: opts.sequence_number,
+        };
+        let payload = ipv4::Payload::ICMP(icmp::Packet::echo_request(echo, &opts.payload));
+        self.send_ipv4(payload, &dest).await?;
+
+        rx.recv_async().await?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        expectation::{
+            Options,
+            RecvError,
+        },
+        link,
+        sim::{
+            fixture::*,
+            Conditions,
+            Host,
+            Network,
+        },
+    };
+    use std::{
+        sync::Arc,
+        time::Duration,
+    };
+
+    #[test]
+    fn async_ping() {
+        let (net, wan, iface) = routed();
+        net.host(wan, Host { echo: false, ..Host::new(mac(106), ip("10.1.0.6")) });
+        let iface = AsyncInterface::from(iface);
+
+        let reply = futures::executor::block_on(iface.ping(ip("10.1.0.5"), &PingOptions::default())).unwrap();
+        assert_eq!(reply.src, ip("10.1.0.5"));
+        assert_eq!(reply.ttl, 63);
+
+        let opts = PingOptions {
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let res = futures::executor::block_on(iface.ping(ip("10.1.0.6"), &opts));
+        assert!(matches!(res, Err(error::Error::Expectation(RecvError::Expired))));
+
+        let res = futures::executor::block_on(iface.ping(ip("10.1.0.99"), &opts));
+        match res {
+            Err(error::Error::Icmp {
+                from,
+                typ: icmp::Type::DestinationUnreachable(icmp::DestinationUnreachable::HostUnreachable),
+            }) => assert_eq!(from, ip("10.0.0.1")),
+            res => panic!("expected host unreachable, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn expectation_stream_ends_after_timeout() {
+        use futures::stream::StreamExt;
+
+        let net = Network::new(0);
+        let lan = net.segment(Conditions::default());
+        net.host(lan, Host::new(mac(1), ip("10.0.0.1")));
+        let port = Arc::new(net.port(lan, mac(2), ip("10.0.0.2")));
+
+        let iface = AsyncInterface::from(Interface::open_ethernet(port.clone(), nic("10.0.0.2", 2, "10.0.0.1")).unwrap());
+        let stream = iface
+            .expect_arp(Options::forever().timeout(Duration::from_millis(200)), |packet| {
+                Some(packet.sender_ip_addr)
+            })
+            .unwrap();
+        link::Link::send(port.as_ref(), &arp_request(&nic("10.0.0.2", 2, "10.0.0.1"))).unwrap();
+
+        let items: Vec<_> = futures::executor::block_on(stream.collect());
+        assert_eq!(items, vec![Ok(ip("10.0.0.1")), Err(RecvError::Expired)]);
+    }
+}
@@ -35,4 +35,30 @@ impl Blob {
         use cookie_factory::combinator::slice;
         slice(&self.0)
     }
-}
\ No newline at end of file
+}
+
+/// As a hex string in human-readable formats, as bytes otherwise.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Blob {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        if s.is_human_readable() {
+            s.serialize_str(&hex::encode(&self.0))
+        } else {
+            s.serialize_bytes(&self.0)
+        }
+    }
+}
+
+/// Whitespace in hex strings is ignored, e.g. "08 00 f7 ff".
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Blob {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        if d.is_human_readable() {
+            let s = String::deserialize(d)?;
+            let digits: String = s.split_whitespace().collect();
+            hex::decode(digits).map(Self).map_err(serde::de::Error::custom)
+        } else {
+            Vec::deserialize(d).map(Self)
+        }
+    }
+}
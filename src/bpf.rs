@@ -0,0 +1,270 @@
+use crate::{
+    ethernet,
+    ipv4,
+};
+
+// classic BPF opcodes, see <pcap/bpf.h>
+const BPF_LD: u16 = 0x00;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+
+const BPF_W: u16 = 0x00;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+const BPF_ABS: u16 = 0x20;
+
+const BPF_JEQ: u16 = 0x10;
+const BPF_K: u16 = 0x00;
+
+/// How much of an accepted packet we ask the kernel to hand over.
+const SNAPLEN: u32 = 65535;
+
+/// A single classic BPF instruction, laid out as `struct bpf_insn`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program(pub Vec<Instruction>);
+
+impl Program {
+    /// Runs the program on `packet` the way the kernel would, returning
+    /// how many bytes of it to keep. Only knows the instructions
+    /// `Filter::compile` emits; reading past the end rejects the packet.
+    pub fn run(&self, packet: &[u8]) -> u32 {
+        let load = |size: u16, k: u32| -> Option<u32> {
+            let k = k as usize;
+            Some(match size {
+                BPF_W => u32::from_be_bytes(packet.get(k..k + 4)?.try_into().ok()?),
+                BPF_H => u16::from_be_bytes(packet.get(k..k + 2)?.try_into().ok()?) as u32,
+                _ => *packet.get(k)? as u32,
+            })
+        };
+
+        let mut a = 0;
+        let mut pc = 0;
+        while let Some(ins) = self.0.get(pc) {
+            pc += 1;
+            match ins.code & 0x07 {
+                BPF_LD => match load(ins.code & 0x18, ins.k) {
+                    Some(x) => a = x,
+                    None => return 0,
+                },
+                BPF_JMP => pc += if a == ins.k { ins.jt } else { ins.jf } as usize,
+                BPF_RET => return ins.k,
+                _ => return 0,
+            }
+        }
+        0
+    }
+}
+
+/// Describes which frames the capture thread is interested in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    pub mac: ethernet::Addr,
+    pub ip: ipv4::Addr,
+    pub protocols: Vec<ipv4::Protocol>,
+    /// Lets ARP through whoever it's sent to, not only us.
+    pub all_arp: bool,
+    /// Lets everything through, for those who want raw frames.
+    pub accept_all: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    Next,
+    Accept,
+    Reject,
+    At(usize),
+}
+
+enum Op {
+    Load(u16, u32),
+    JumpIfEqual(u32, Target, Target),
+    Return(u32),
+}
+
+impl Filter {
+    /// Lets through frames sent to our MAC (or broadcast) that carry
+    /// either ARP, or IPv4 addressed to us with one of `protocols`,
+    /// and with `all_arp`, ARP sent to anyone.
+    pub fn compile(&self) -> Program {
+        use Target::*;
+
+        if self.accept_all {
+            return Program(vec![Instruction { code: BPF_RET | BPF_K, jt: 0, jf: 0, k: SNAPLEN }]);
+        }
+
+        let mac = &self.mac.0;
+        let mac_hi = u16::from_be_bytes([mac[0], mac[1]]) as u32;
+        let mac_lo = u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]);
+        let ip = u32::from_be_bytes(self.ip.0);
+
+        let mut ops = Vec::new();
+        if self.all_arp {
+            ops.push(Op::Load(BPF_H, 12));
+            ops.push(Op::JumpIfEqual(ethernet::EtherType::ARP as u32, Accept, Next));
+        }
+        let start = ops.len();
+        // index of the "ldh [12]" below, where both MAC checks end up
+        let ether_type = start + 7;
+
+        ops.extend([
+            // destination MAC: ours...
+            Op::Load(BPF_W, 2),
+            Op::JumpIfEqual(mac_lo, Next, At(start + 4)),
+            Op::Load(BPF_H, 0),
+            Op::JumpIfEqual(mac_hi, At(ether_type), Reject),
+            // ...or broadcast
+            Op::JumpIfEqual(0xFFFF_FFFF, Next, Reject),
+            Op::Load(BPF_H, 0),
+            Op::JumpIfEqual(0xFFFF, Next, Reject),
+            // EtherType
+            Op::Load(BPF_H, 12),
+            Op::JumpIfEqual(ethernet::EtherType::ARP as u32, Accept, Next),
+            Op::JumpIfEqual(ethernet::EtherType::IPv4 as u32, Next, Reject),
+            // IPv4 destination
+            Op::Load(BPF_W, 14 + 16),
+            Op::JumpIfEqual(ip, Next, Reject),
+            // IPv4 protocol
+            Op::Load(BPF_B, 14 + 9),
+        ]);
+        for protocol in self.protocols.iter() {
+            ops.push(Op::JumpIfEqual(*protocol as u32, Accept, Next));
+        }
+
+        let reject = ops.len();
+        let accept = reject + 1;
+        ops.push(Op::Return(0));
+        ops.push(Op::Return(SNAPLEN));
+
+        let offset = |from: usize, to: Target| -> u8 {
+            let to = match to {
+                Next => from + 1,
+                Accept => accept,
+                Reject => reject,
+                At(x) => x,
+            };
+            // jumps are relative to the next instruction
+            (to - from - 1) as u8
+        };
+
+        let instructions = ops
+            .iter()
+            .enumerate()
+            .map(|(i, op)| match *op {
+                Op::Load(size, k) => Instruction { code: BPF_LD | size | BPF_ABS, jt: 0, jf: 0, k },
+                Op::JumpIfEqual(k, jt, jf) => Instruction {
+                    code: BPF_JMP | BPF_JEQ | BPF_K,
+                    jt: offset(i, jt),
+                    jf: offset(i, jf),
+                    k,
+                },
+                Op::Return(k) => Instruction { code: BPF_RET | BPF_K, jt: 0, jf: 0, k },
+            })
+            .collect();
+
+        Program(instructions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OUR_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+    const OUR_IP: [u8; 4] = [192, 168, 1, 10];
+
+    fn filter(protocols: Vec<ipv4::Protocol>) -> Program {
+        Filter {
+            mac: ethernet::Addr(OUR_MAC),
+            ip: ipv4::Addr(OUR_IP),
+            protocols,
+            all_arp: false,
+            accept_all: false,
+        }
+        .compile()
+    }
+
+    fn frame(dst_mac: [u8; 6], ether_type: u16, dst_ip: [u8; 4], protocol: u8) -> Vec<u8> {
+        let mut f = vec![0u8; 14 + 20];
+        f[..6].copy_from_slice(&dst_mac);
+        f[12..14].copy_from_slice(&ether_type.to_be_bytes());
+        f[14 + 9] = protocol;
+        f[14 + 16..14 + 20].copy_from_slice(&dst_ip);
+        f
+    }
+
+    #[test]
+    fn accepts_expected_ipv4_protocol() {
+        let p = filter(vec![ipv4::Protocol::ICMP]);
+        assert_eq!(p.run(&frame(OUR_MAC, 0x0800, OUR_IP, 1)), SNAPLEN);
+    }
+
+    #[test]
+    fn rejects_unexpected_ipv4_protocol() {
+        let p = filter(vec![ipv4::Protocol::ICMP]);
+        assert_eq!(p.run(&frame(OUR_MAC, 0x0800, OUR_IP, 6)), 0);
+        assert_eq!(filter(vec![]).run(&frame(OUR_MAC, 0x0800, OUR_IP, 1)), 0);
+    }
+
+    #[test]
+    fn rejects_other_destinations() {
+        let p = filter(vec![ipv4::Protocol::ICMP]);
+        assert_eq!(p.run(&frame([0x02, 0, 0, 0, 0, 2], 0x0800, OUR_IP, 1)), 0);
+        assert_eq!(p.run(&frame([0x03, 0, 0, 0, 0, 1], 0x0800, OUR_IP, 1)), 0);
+        assert_eq!(p.run(&frame(OUR_MAC, 0x0800, [192, 168, 1, 11], 1)), 0);
+    }
+
+    #[test]
+    fn accepts_arp_to_us_or_broadcast() {
+        let p = filter(vec![]);
+        assert_eq!(p.run(&frame(OUR_MAC, 0x0806, [0; 4], 0)), SNAPLEN);
+        assert_eq!(p.run(&frame([0xFF; 6], 0x0806, [0; 4], 0)), SNAPLEN);
+        assert_eq!(p.run(&frame([0x02, 0, 0, 0, 0, 2], 0x0806, [0; 4], 0)), 0);
+    }
+
+    #[test]
+    fn all_arp_lets_arp_to_others_through() {
+        let p = Filter {
+            mac: ethernet::Addr(OUR_MAC),
+            ip: ipv4::Addr(OUR_IP),
+            protocols: vec![ipv4::Protocol::ICMP],
+            all_arp: true,
+            accept_all: false,
+        }
+        .compile();
+        assert_eq!(p.run(&frame([0x02, 0, 0, 0, 0, 2], 0x0806, [0; 4], 0)), SNAPLEN);
+        assert_eq!(p.run(&frame([0xFF; 6], 0x0806, [0; 4], 0)), SNAPLEN);
+        // the rest is filtered as usual
+        assert_eq!(p.run(&frame(OUR_MAC, 0x0800, OUR_IP, 1)), SNAPLEN);
+        assert_eq!(p.run(&frame([0xFF; 6], 0x0800, OUR_IP, 1)), SNAPLEN);
+        assert_eq!(p.run(&frame([0x02, 0, 0, 0, 0, 2], 0x0800, OUR_IP, 1)), 0);
+        assert_eq!(p.run(&frame(OUR_MAC, 0x0800, OUR_IP, 6)), 0);
+    }
+
+    #[test]
+    fn accept_all_lets_anything_through() {
+        let p = Filter {
+            mac: ethernet::Addr(OUR_MAC),
+            ip: ipv4::Addr(OUR_IP),
+            protocols: vec![],
+            all_arp: false,
+            accept_all: true,
+        }
+        .compile();
+        assert_eq!(p.run(&frame([0x02, 0, 0, 0, 0, 2], 0x86DD, [0; 4], 0)), SNAPLEN);
+    }
+
+    #[test]
+    fn rejects_other_ether_types() {
+        let p = filter(vec![ipv4::Protocol::ICMP]);
+        assert_eq!(p.run(&frame(OUR_MAC, 0x86DD, OUR_IP, 1)), 0);
+    }
+}
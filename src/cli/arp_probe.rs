@@ -0,0 +1,62 @@
+use crate::cli::{
+    print_json,
+    Format,
+};
+use oppa::{
+    acd::{
+        AddressProbe,
+        Conflict,
+    },
+    ipv4,
+};
+use serde_json::json;
+use std::process;
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// The address we'd like to use
+    addr: ipv4::Addr,
+
+    /// Number of probes to send
+    #[clap(long, default_value_t = 3)]
+    probes: usize,
+
+    /// Announce the address with gratuitous ARP if it's free
+    #[clap(long)]
+    announce: bool,
+
+    /// Announce the address without probing for it first
+    #[clap(long, conflicts_with_all = &["probes", "announce"])]
+    announce_only: bool,
+}
+
+pub fn run(opts: Options, format: Format) -> Result<(), Box<dyn std::error::Error>> {
+    let probe = AddressProbe::builder(opts.addr).probes(opts.probes).build()?;
+
+    if !opts.announce_only {
+        if format == Format::Text {
+            println!("Probing for {:?}...", opts.addr);
+        }
+        let conflict = probe.probe()?;
+        match (format, conflict) {
+            (Format::Json, conflict) => {
+                print_json(json!({ "type": "probe", "addr": opts.addr, "conflict": conflict }), json!({}))
+            }
+            (Format::Text, Some(Conflict::InUse(mac))) => println!("{:?} is in use by {}", opts.addr, mac),
+            (Format::Text, Some(Conflict::Probing(mac))) => println!("{} is probing for {:?} too", mac, opts.addr),
+            (Format::Text, None) => println!("{:?} is free", opts.addr),
+        }
+        if conflict.is_some() {
+            process::exit(1);
+        }
+    }
+
+    if opts.announce || opts.announce_only {
+        probe.announce()?;
+        match format {
+            Format::Text => println!("Announced {:?}", opts.addr),
+            Format::Json => print_json(json!({ "type": "announced", "addr": opts.addr }), json!({})),
+        }
+    }
+    Ok(())
+}
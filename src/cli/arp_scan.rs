@@ -0,0 +1,66 @@
+use crate::cli::{
+    parse_duration,
+    print_json,
+    Format,
+};
+use oppa::{
+    arp_scan::ArpScan,
+    ipv4,
+    Interface,
+};
+use serde_json::json;
+use std::time;
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// Block of addresses to scan [default: the subnet of the interface]
+    cidr: Option<ipv4::Cidr>,
+
+    /// ARP requests sent per second
+    #[clap(long, default_value_t = 100)]
+    rate: u32,
+
+    /// How many more times to ask the hosts that didn't answer
+    #[clap(short = 'r', long, default_value_t = 0)]
+    retries: usize,
+
+    /// How long to wait for replies after the last request
+    #[clap(short = 'w', long, parse(try_from_str = parse_duration), default_value = "1s")]
+    timeout: time::Duration,
+}
+
+pub fn run(opts: Options, format: Format) -> Result<(), Box<dyn std::error::Error>> {
+    let iface = Interface::open_default()?;
+    let cidr = match opts.cidr.or_else(|| iface.subnet()) {
+        Some(cidr) => cidr,
+        None => return Err("couldn't tell the subnet of the interface, please give one".into()),
+    };
+
+    let scan = ArpScan::builder(cidr)
+        .interface(iface)
+        .rate(opts.rate)
+        .retries(opts.retries)
+        .timeout(opts.timeout)
+        .build()?;
+    if format == Format::Text {
+        println!("Scanning {} ({} hosts):", cidr, cidr.hosts().count());
+    }
+
+    let bindings = scan.run()?;
+    match format {
+        Format::Text => {
+            println!("\n{:<15}  MAC", "IP");
+            for binding in &bindings {
+                println!("{:<15}  {}", format!("{:?}", binding.ip), binding.mac);
+            }
+            println!("{} stations found", bindings.len());
+        }
+        Format::Json => {
+            for binding in &bindings {
+                print_json(binding, json!({ "type": "binding" }));
+            }
+            print_json(json!({ "type": "summary", "cidr": cidr, "stations": bindings.len() }), json!({}));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use crate::cli::{
+    parse_duration,
+    print_json,
+    Format,
+};
+use oppa::{
+    arping::{
+        Arping,
+        ArpingEvent,
+    },
+    ethernet,
+    ipv4,
+};
+use serde_json::json;
+use std::{
+    process,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// An address on the local network
+    target: ipv4::Addr,
+
+    /// Number of ARP requests to send
+    #[clap(short = 'n', short_alias = 'c', long, default_value_t = 4)]
+    count: usize,
+
+    /// Send requests until interrupted
+    #[clap(short = 't', long, conflicts_with = "count")]
+    continuous: bool,
+
+    /// How long to wait for each reply
+    #[clap(short = 'w', long, parse(try_from_str = parse_duration), default_value = "1s")]
+    timeout: time::Duration,
+
+    /// Time between two requests
+    #[clap(long, parse(try_from_str = parse_duration), default_value = "1s")]
+    interval: time::Duration,
+}
+
+/// What's printed at the end, or on Ctrl+C.
+struct Summary {
+    target: ipv4::Addr,
+    format: Format,
+    sent: usize,
+    received: usize,
+    // everyone who answered, in the order they first did
+    macs: Vec<ethernet::Addr>,
+}
+
+impl Summary {
+    /// Counts `event` in, returning whether it came from a MAC
+    /// other than the first one that answered.
+    fn record(&mut self, event: &ArpingEvent) -> bool {
+        match *event {
+            ArpingEvent::Reply(ref reply) => {
+                self.sent = self.sent.max(reply.sequence_number as usize + 1);
+                self.received += 1;
+                if self.macs.contains(&reply.mac) {
+                    return false;
+                }
+                self.macs.push(reply.mac);
+                self.macs.len() > 1
+            }
+            ArpingEvent::Duplicate(_) => false,
+            ArpingEvent::Timeout { sequence_number } => {
+                self.sent = self.sent.max(sequence_number as usize + 1);
+                false
+            }
+        }
+    }
+
+    fn print(&self) {
+        if self.format == Format::Json {
+            let summary = json!({
+                "type": "summary",
+                "target": self.target,
+                "transmitted": self.sent,
+                "received": self.received,
+                "macs": self.macs,
+            });
+            print_json(summary, json!({}));
+            return;
+        }
+
+        println!(
+            "\n--- {:?} arping statistics ---\n{} requests transmitted, {} replies received",
+            self.target, self.sent, self.received
+        );
+        if self.macs.len() > 1 {
+            let macs: Vec<_> = self.macs.iter().map(|mac| mac.to_string()).collect();
+            println!("Warning: {:?} is claimed by several MACs: {}", self.target, macs.join(", "));
+        }
+    }
+
+    fn exit_status(&self) -> i32 {
+        if self.received > 0 {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+pub fn run(opts: Options, format: Format) -> Result<(), Box<dyn std::error::Error>> {
+    let mut builder = Arping::builder(opts.target)
+        .count(opts.count)
+        .timeout(opts.timeout)
+        .interval(opts.interval);
+    if opts.continuous {
+        builder = builder.continuous();
+    }
+    let arping = builder.build()?;
+    let requests = arping.iter()?;
+    if format == Format::Text {
+        println!("ARPING {:?}:", opts.target);
+    }
+
+    let summary = Arc::new(Mutex::new(Summary {
+        target: opts.target,
+        format,
+        sent: 0,
+        received: 0,
+        macs: Vec::new(),
+    }));
+    {
+        let summary = summary.clone();
+        ctrlc::set_handler(move || {
+            let summary = summary.lock().unwrap();
+            summary.print();
+            process::exit(summary.exit_status());
+        })?;
+    }
+
+    for event in requests {
+        let mut summary = summary.lock().unwrap();
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                match format {
+                    Format::Text => eprintln!("{:?}: {}", opts.target, e),
+                    Format::Json => {
+                        let error = json!({ "type": "send_error", "message": e.to_string() });
+                        print_json(error, json!({ "target": opts.target }));
+                    }
+                }
+                continue;
+            }
+        };
+        let other_mac = summary.record(&event);
+
+        match (format, event) {
+            (Format::Json, event @ ArpingEvent::Timeout { .. }) => print_json(event, json!({ "target": opts.target })),
+            (Format::Json, event) => print_json(event, json!({ "target": opts.target, "other_mac": other_mac })),
+            (Format::Text, ArpingEvent::Reply(reply)) => println!(
+                "Reply from {:?} [{}]: index={} time={:?}{}",
+                opts.target,
+                reply.mac,
+                reply.sequence_number,
+                reply.rtt,
+                if other_mac { " (another MAC!)" } else { "" },
+            ),
+            (Format::Text, ArpingEvent::Duplicate(reply)) => println!(
+                "Reply from {:?} [{}]: index={} time={:?} (DUP!)",
+                opts.target, reply.mac, reply.sequence_number, reply.rtt,
+            ),
+            (Format::Text, ArpingEvent::Timeout { sequence_number }) => {
+                println!("Request timed out: index={}", sequence_number)
+            }
+        }
+    }
+
+    let summary = summary.lock().unwrap();
+    summary.print();
+    process::exit(summary.exit_status());
+}
@@ -0,0 +1,73 @@
+use crate::cli::{
+    print_json,
+    Format,
+};
+use oppa::{
+    arpwatch::{
+        Alert,
+        ArpWatch,
+        Database,
+    },
+    Interface,
+};
+use serde_json::json;
+use std::{
+    path::PathBuf,
+    process,
+    time,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// Where the bindings seen so far are kept between runs
+    #[clap(long, default_value = "arpwatch.dat")]
+    db: PathBuf,
+
+    /// Run this shell command on each alert instead of printing it.
+    /// It gets OPPA_ALERT, OPPA_IP, OPPA_MAC and OPPA_OLD_MAC in its environment.
+    #[clap(long)]
+    hook: Option<String>,
+}
+
+pub fn run(opts: Options, format: Format) -> Result<(), Box<dyn std::error::Error>> {
+    let database = Database::load(&opts.db)?;
+    let iface = Interface::open_default()?;
+    let mut watch = ArpWatch::new(&iface, database)?;
+    eprintln!(
+        "Watching ARP traffic, {} bindings known from {}",
+        watch.database().len(),
+        opts.db.display()
+    );
+
+    while let Some(alert) = watch.next() {
+        // saved right away, in case we get killed
+        watch.database().save(&opts.db)?;
+
+        match opts.hook {
+            Some(ref hook) => run_hook(hook, &alert),
+            None if format == Format::Json => print_json(&alert, json!({ "type": "alert" })),
+            None => {
+                let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default();
+                println!("[{}] {}", now.as_secs(), alert);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_hook(hook: &str, alert: &Alert) {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let binding = alert.binding();
+    let status = process::Command::new(shell)
+        .args([flag, hook])
+        .env("OPPA_ALERT", alert.kind())
+        .env("OPPA_IP", format!("{:?}", binding.ip))
+        .env("OPPA_MAC", binding.mac.to_string())
+        .env("OPPA_OLD_MAC", alert.old_mac().map(|mac| mac.to_string()).unwrap_or_default())
+        .status();
+    match status {
+        Ok(status) if !status.success() => eprintln!("hook failed on {}: {}", alert, status),
+        Err(e) => eprintln!("couldn't run hook on {}: {}", alert, e),
+        Ok(_) => {}
+    }
+}
@@ -0,0 +1,172 @@
+use crate::cli::{
+    print_json,
+    Format,
+};
+use oppa::{
+    dissect,
+    link,
+    pcap_file,
+};
+use serde_json::json;
+use std::{
+    fs,
+    io::{
+        self,
+        Read,
+    },
+    path::PathBuf,
+    process,
+    time,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// A pcap file, or a file of frames in hex separated by blank lines,
+    /// or - for standard input
+    #[clap(default_value = "-")]
+    input: PathBuf,
+
+    /// A frame in hex, to decode instead of a file
+    #[clap(short = 'x', long, conflicts_with = "input")]
+    hex: Option<String>,
+
+    /// Hex frames start with an IPv4 header rather than an Ethernet one
+    #[clap(long)]
+    ipv4: bool,
+}
+
+/// A frame to decode, and what we know about it.
+struct Frame {
+    captured_at: Option<time::Duration>,
+    original_len: Option<u32>,
+    data: Result<Vec<u8>, String>,
+}
+
+pub fn run(opts: Options, format: Format) -> Result<(), Box<dyn std::error::Error>> {
+    let mut layer = if opts.ipv4 {
+        link::Layer::IPv4
+    } else {
+        link::Layer::Ethernet
+    };
+
+    let frames = match opts.hex {
+        Some(hex) => vec![hex_frame(&hex)],
+        None => {
+            let mut bytes = Vec::new();
+            if opts.input.as_os_str() == "-" {
+                io::stdin().read_to_end(&mut bytes)?;
+            } else {
+                bytes = fs::read(&opts.input)?;
+            }
+
+            if pcap_file::is_pcap(&bytes) {
+                let reader = pcap_file::Reader::new(&bytes[..])?;
+                layer = reader
+                    .layer()
+                    .ok_or_else(|| format!("unsupported pcap link type {}", reader.link_type()))?;
+                let mut frames = Vec::new();
+                for record in reader {
+                    let record = record?;
+                    frames.push(Frame {
+                        captured_at: Some(record.timestamp),
+                        original_len: Some(record.original_len),
+                        data: Ok(record.data),
+                    });
+                }
+                frames
+            } else {
+                let text = String::from_utf8(bytes).map_err(|_| "the input is neither a pcap file nor hex text")?;
+                hex_blocks(&text).iter().map(|block| hex_frame(block)).collect()
+            }
+        }
+    };
+
+    let mut failed = false;
+    for (i, frame) in frames.into_iter().enumerate() {
+        let index = i + 1;
+        let res = frame
+            .data
+            .as_ref()
+            .map_err(|e| e.clone())
+            .and_then(|data| dissect::dissect(data, layer).map_err(|e| e.to_string()));
+        failed |= res.is_err();
+        let len = frame.data.as_ref().map(|data| data.len()).unwrap_or(0);
+
+        match format {
+            Format::Json => {
+                let captured_at = frame.captured_at.map(|t| t.as_secs_f64());
+                let fields = json!({ "index": index, "captured_at": captured_at });
+                match res {
+                    Ok(layers) => print_json(
+                        json!({ "type": "frame", "length": len, "original_length": frame.original_len, "layers": layers }),
+                        fields,
+                    ),
+                    Err(message) => print_json(json!({ "type": "parse_error", "message": message }), fields),
+                }
+            }
+            Format::Text => {
+                print!("Frame {}: {} bytes", index, len);
+                if let Some(original_len) = frame.original_len.filter(|&l| l as usize != len) {
+                    print!(" of {}", original_len);
+                }
+                if let Some(t) = frame.captured_at {
+                    print!(", captured at {}.{:06}", t.as_secs(), t.subsec_micros());
+                }
+                println!();
+                match res {
+                    Ok(layers) => print_layers(&layers),
+                    Err(message) => println!("{}", message.trim_end()),
+                }
+                println!();
+            }
+        }
+    }
+
+    process::exit(if failed { 1 } else { 0 });
+}
+
+/// Prints the fields of each layer, one per line, under its name.
+pub fn print_layers(layers: &[dissect::Layer]) {
+    for layer in layers {
+        println!("{} [{}..{}]", layer.name, layer.range.start, layer.range.end);
+        for field in &layer.fields {
+            let range = format!("{}..{}", field.range.start, field.range.end);
+            println!("    {:<16} {:<8} {}", field.name, range, field.value);
+        }
+    }
+}
+
+/// Splits `text` at blank lines, dropping `#` comments.
+fn hex_blocks(text: &str) -> Vec<String> {
+    let mut blocks = vec![String::new()];
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            blocks.push(String::new());
+            continue;
+        }
+        let block = blocks.last_mut().unwrap();
+        block.push_str(line.split('#').next().unwrap_or(""));
+        block.push(' ');
+    }
+    blocks.retain(|block| !block.trim().is_empty());
+    blocks
+}
+
+/// Takes hex digits with any whitespace, ':' or '-' between them.
+fn hex_frame(s: &str) -> Frame {
+    let digits: Vec<_> = s.chars().filter(|c| !c.is_whitespace() && !matches!(c, ':' | '-')).collect();
+    let data = if let Some(c) = digits.iter().find(|c| !c.is_ascii_hexdigit()) {
+        Err(format!("invalid hex digit {:?}", c))
+    } else if digits.len() % 2 == 1 {
+        Err("odd number of hex digits".to_owned())
+    } else {
+        let digit = |c: char| c.to_digit(16).unwrap() as u8;
+        Ok(digits.chunks(2).map(|pair| digit(pair[0]) << 4 | digit(pair[1])).collect())
+    };
+
+    Frame {
+        captured_at: None,
+        original_len: None,
+        data,
+    }
+}
@@ -0,0 +1,88 @@
+//! The subcommands of the `oppa` binary.
+
+use oppa::ipv4;
+use serde::Serialize;
+use std::{
+    fs,
+    io,
+    path::Path,
+    time,
+};
+
+pub mod arp_probe;
+pub mod arp_scan;
+pub mod arping;
+pub mod arpwatch;
+pub mod decode;
+pub mod ping;
+pub mod sniff;
+pub mod sweep;
+
+/// How results get printed.
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// Prints `event` as one line of `--format json` output: its JSON object,
+/// plus the members of `fields` and a "timestamp" in unix seconds.
+/// A result that can't be serialized is reported on stderr instead.
+pub fn print_json(event: impl Serialize, fields: serde_json::Value) {
+    let mut line = match serde_json::to_value(event) {
+        Ok(line) => line,
+        Err(e) => {
+            eprintln!("Can't print a result as JSON: {}", e);
+            return;
+        }
+    };
+    if let (Some(line), serde_json::Value::Object(fields)) = (line.as_object_mut(), fields) {
+        line.extend(fields);
+    }
+    let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default();
+    line["timestamp"] = serde_json::json!(now.as_secs_f64());
+    println!("{}", line);
+}
+
+/// Reads addresses one per line, skipping blank lines and `#` comments.
+pub fn read_hosts(path: &Path) -> Result<Vec<ipv4::Addr>, Box<dyn std::error::Error>> {
+    let text = if path.as_os_str() == "-" {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(path)?
+    };
+
+    let mut hosts = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let host = line
+            .parse()
+            .map_err(|e| format!("{}:{}: {:?}: {}", path.display(), i + 1, line, e))?;
+        hosts.push(host);
+    }
+    Ok(hosts)
+}
+
+/// Parses "500ms", "2s" or a plain number of seconds like "1.5".
+pub fn parse_duration(s: &str) -> Result<time::Duration, String> {
+    let (number, unit) = match s.strip_suffix("ms") {
+        Some(number) => (number, 1e-3),
+        None => (s.strip_suffix('s').unwrap_or(s), 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(x) if x >= 0.0 && x.is_finite() => Ok(time::Duration::from_secs_f64(x * unit)),
+        _ => Err(format!("invalid duration {:?}", s)),
+    }
+}
+
+/// Parses a byte in decimal, or in hexadecimal with a 0x prefix.
+pub fn parse_byte(s: &str) -> Result<u8, String> {
+    let res = match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    res.map_err(|e| format!("invalid byte {:?}: {}", s, e))
+}
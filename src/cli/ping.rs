@@ -0,0 +1,366 @@
+use crate::cli::{
+    parse_byte,
+    parse_duration,
+    print_json,
+    read_hosts,
+    Format,
+};
+use oppa::{
+    error,
+    ipv4,
+    pinger::{
+        self,
+        PingEvent,
+        Statistics,
+    },
+    Pinger,
+};
+use serde_json::json;
+use std::{
+    collections::HashSet,
+    io::{
+        self,
+        Write,
+    },
+    path::PathBuf,
+    process,
+    sync::{
+        mpsc,
+        Arc,
+        Mutex,
+    },
+    time,
+};
+
+const DEFAULT_INTERVAL: time::Duration = time::Duration::from_secs(1);
+const FLOOD_INTERVAL: time::Duration = time::Duration::from_millis(10);
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    #[clap(required_unless_present = "file")]
+    dests: Vec<ipv4::Addr>,
+
+    /// Also ping the hosts listed in a file, one per line ("-" for stdin)
+    #[clap(long)]
+    file: Option<PathBuf>,
+
+    /// Number of echo requests to send
+    #[clap(short = 'n', short_alias = 'c', long, default_value_t = 4)]
+    count: usize,
+
+    /// Ping until interrupted
+    #[clap(short = 't', long, conflicts_with = "count")]
+    continuous: bool,
+
+    /// Size of the payload, in bytes
+    #[clap(short = 'l', short_alias = 's', long)]
+    size: Option<usize>,
+
+    /// Time to live of the requests
+    #[clap(short = 'i', long)]
+    ttl: Option<u8>,
+
+    /// How long to wait for each reply, e.g. "500ms" or "2s"
+    #[clap(short = 'w', long, parse(try_from_str = parse_duration), default_value = "3s")]
+    timeout: time::Duration,
+
+    /// Time between two requests [default: 1s, or 10ms when flooding]
+    #[clap(long, parse(try_from_str = parse_duration))]
+    interval: Option<time::Duration>,
+
+    /// Send the next request as soon as the previous one is answered
+    #[clap(short = 'A', long)]
+    adaptive: bool,
+
+    /// Ping as fast as replies come back, printing a dot per request
+    /// and a backspace per reply, so the dots left are the lost ones
+    #[clap(long)]
+    flood: bool,
+
+    /// Set the don't fragment flag
+    #[clap(short = 'f', long)]
+    dont_fragment: bool,
+
+    /// Type of service byte, e.g. 0xb8
+    #[clap(short = 'v', long, parse(try_from_str = parse_byte))]
+    tos: Option<u8>,
+
+    /// Only print the first line and the summary
+    #[clap(short = 'q', long)]
+    quiet: bool,
+
+    /// Prefix each line with a unix timestamp
+    #[clap(short = 'D', long)]
+    timestamps: bool,
+
+    /// Stop after this long, however many requests were sent
+    #[clap(long, parse(try_from_str = parse_duration))]
+    deadline: Option<time::Duration>,
+
+    /// Only list the hosts that replied
+    #[clap(short = 'a', long, conflicts_with = "unreachable")]
+    alive: bool,
+
+    /// Only list the hosts that didn't reply
+    #[clap(short = 'u', long)]
+    unreachable: bool,
+}
+
+impl Options {
+    fn pinger(&self, dest: ipv4::Addr) -> pinger::PingerBuilder {
+        let default_interval = if self.flood { FLOOD_INTERVAL } else { DEFAULT_INTERVAL };
+        let mut builder = Pinger::builder(dest)
+            .count(self.count)
+            .timeout(self.timeout)
+            .interval(self.interval.unwrap_or(default_interval))
+            .dont_fragment(self.dont_fragment);
+        if self.adaptive || self.flood {
+            builder = builder.adaptive();
+        }
+        if self.flood {
+            builder = builder.sent_events();
+        }
+        if self.continuous {
+            builder = builder.continuous();
+        }
+        if let Some(size) = self.size {
+            builder = builder.payload_size(size);
+        }
+        if let Some(ttl) = self.ttl {
+            builder = builder.ttl(ttl);
+        }
+        if let Some(tos) = self.tos {
+            builder = builder.tos(tos);
+        }
+        if let Some(deadline) = self.deadline {
+            builder = builder.deadline(deadline);
+        }
+        builder
+    }
+
+    /// Whether only a list of hosts gets printed, at the end.
+    fn listing(&self) -> bool {
+        self.alive || self.unreachable
+    }
+}
+
+/// What's printed at the end, or on a signal.
+struct Report {
+    dests: Vec<ipv4::Addr>,
+    stats: Vec<Statistics>,
+    alive: bool,
+    unreachable: bool,
+    format: Format,
+}
+
+impl Report {
+    fn print(&self) {
+        if self.format == Format::Json {
+            for (dest, stats) in self.dests.iter().zip(&self.stats) {
+                print_json(stats, json!({ "type": "summary", "dest": dest, "alive": stats.received > 0 }));
+            }
+            return;
+        }
+
+        if self.alive || self.unreachable {
+            for (dest, stats) in self.dests.iter().zip(&self.stats) {
+                if (stats.received > 0) == self.alive {
+                    println!("{:?}", dest);
+                }
+            }
+            return;
+        }
+
+        if let [dest] = self.dests[..] {
+            println!("\n--- {:?} ping statistics ---\n{}", dest, self.stats[0]);
+            return;
+        }
+
+        let ms = |d: Option<time::Duration>| match d {
+            Some(d) => format!("{:.3}", d.as_secs_f64() * 1000.0),
+            None => "-".to_string(),
+        };
+        println!(
+            "\n{:<15} {:>6} {:>6} {:>5} {:>9} {:>9} {:>9}  STATUS",
+            "HOST", "SENT", "RECV", "LOSS", "MIN", "AVG", "MAX"
+        );
+        for (dest, stats) in self.dests.iter().zip(&self.stats) {
+            println!(
+                "{:<15} {:>6} {:>6} {:>4}% {:>9} {:>9} {:>9}  {}",
+                format!("{:?}", dest),
+                stats.transmitted,
+                stats.received,
+                stats.loss().round(),
+                ms(stats.rtt_min()),
+                ms(stats.rtt_avg()),
+                ms(stats.rtt_max()),
+                if stats.received > 0 { "alive" } else { "unreachable" },
+            );
+        }
+    }
+
+    /// Success once every host replied.
+    fn exit_status(&self) -> i32 {
+        if self.stats.iter().all(|stats| stats.received > 0) {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+pub fn run(opts: Options, format: Format) -> Result<(), Box<dyn std::error::Error>> {
+    let mut dests = opts.dests.clone();
+    if let Some(ref path) = opts.file {
+        dests.extend(read_hosts(path)?);
+    }
+    let mut seen = HashSet::new();
+    dests.retain(|dest| seen.insert(*dest));
+    if dests.is_empty() {
+        return Err("no hosts to ping".into());
+    }
+
+    // all pingers go through one interface, each with its own sequence numbers
+    let iface = pinger::default_interface()?;
+    let identifier = rand::random();
+    let pingers = dests
+        .iter()
+        .map(|&dest| opts.pinger(dest).interface(iface.clone()).identifier(identifier).build())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let payload_size = pingers[0].payload_size();
+    if !opts.listing() && format == Format::Text {
+        match dests[..] {
+            [dest] => println!("Pinging {:?} with {} bytes of data:", dest, payload_size),
+            _ => println!("Pinging {} hosts with {} bytes of data:", dests.len(), payload_size),
+        }
+    }
+
+    let report = Arc::new(Mutex::new(Report {
+        dests: dests.clone(),
+        stats: vec![Statistics::default(); dests.len()],
+        alive: opts.alive,
+        unreachable: opts.unreachable,
+        format,
+    }));
+    {
+        let report = report.clone();
+        ctrlc::set_handler(move || {
+            let report = report.lock().unwrap();
+            report.print();
+            process::exit(report.exit_status());
+        })?;
+    }
+    #[cfg(unix)]
+    print_interim_on_sigquit(report.clone())?;
+
+    crossbeam_utils::thread::scope(|s| -> io::Result<()> {
+        let (tx, rx) = mpsc::channel();
+        for (i, pinger) in pingers.iter().enumerate() {
+            let tx = tx.clone();
+            s.spawn(move |_| {
+                for event in pinger {
+                    if tx.send((i, event)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        for (i, event) in rx {
+            if let Ok(ref event) = event {
+                report.lock().unwrap().stats[i].record(event);
+            }
+            match format {
+                Format::Text => print_event(&opts, dests[i], dests.len() > 1, event)?,
+                Format::Json if !opts.quiet => print_json_event(dests[i], event),
+                Format::Json => {}
+            }
+        }
+        Ok(())
+    })
+    .unwrap_or_else(|e| std::panic::resume_unwind(e))?;
+
+    let report = report.lock().unwrap();
+    report.print();
+    process::exit(report.exit_status());
+}
+
+fn print_event(
+    opts: &Options,
+    dest: ipv4::Addr,
+    prefix_dest: bool,
+    event: Result<PingEvent, error::Error>,
+) -> io::Result<()> {
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => {
+            eprintln!("{:?}: {}", dest, e);
+            return Ok(());
+        }
+    };
+
+    if opts.flood {
+        // like ping -f: a dot goes out with each request and a reply
+        // erases it, so the dots left are the requests that got lost
+        let mark = match event {
+            PingEvent::Sent { .. } => ".",
+            PingEvent::Reply(_) => "\x08 \x08",
+            PingEvent::Error { .. } => "\x08E",
+            PingEvent::Duplicate(_) | PingEvent::Timeout { .. } => return Ok(()),
+        };
+        print!("{}", mark);
+        return io::stdout().flush();
+    }
+
+    let line = match event {
+        PingEvent::Sent { .. } => return Ok(()),
+        PingEvent::Reply(reply) => format!(
+            "Reply from {:?}: icmp_seq={} bytes={} time={:?} TTL={}",
+            reply.src, reply.sequence_number, reply.size, reply.rtt, reply.ttl,
+        ),
+        PingEvent::Duplicate(reply) => format!(
+            "Reply from {:?}: icmp_seq={} bytes={} time={:?} TTL={} (DUP!)",
+            reply.src, reply.sequence_number, reply.size, reply.rtt, reply.ttl,
+        ),
+        PingEvent::Error { sequence_number, from, typ } => {
+            format!("Reply from {:?}: icmp_seq={} {:?}", from, sequence_number, typ)
+        }
+        PingEvent::Timeout { sequence_number } => format!("Request timed out: icmp_seq={}", sequence_number),
+    };
+
+    if opts.quiet || opts.listing() {
+        return Ok(());
+    }
+    let mut prefix = String::new();
+    if opts.timestamps {
+        let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default();
+        prefix += &format!("[{}.{:06}] ", now.as_secs(), now.subsec_micros());
+    }
+    if prefix_dest {
+        prefix += &format!("{:?} : ", dest);
+    }
+    println!("{}{}", prefix, line);
+    Ok(())
+}
+
+/// Events carry the "dest" they're about, since several may be pinged at once.
+fn print_json_event(dest: ipv4::Addr, event: Result<PingEvent, error::Error>) {
+    match event {
+        Ok(event) => print_json(event, json!({ "dest": dest })),
+        Err(e) => print_json(json!({ "type": "send_error", "message": e.to_string() }), json!({ "dest": dest })),
+    }
+}
+
+/// Ctrl+\ prints the statistics so far, as with the system ping.
+#[cfg(unix)]
+fn print_interim_on_sigquit(report: Arc<Mutex<Report>>) -> io::Result<()> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGQUIT])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            report.lock().unwrap().print();
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use crate::cli::{
+    decode::print_layers,
+    print_json,
+    Format,
+};
+use oppa::{
+    dissect,
+    expectation,
+    Interface,
+};
+use serde_json::json;
+use std::{
+    process,
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+    time,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// -v adds IPv4 header fields and checksums to each line,
+    /// -vv a decode of every field
+    #[clap(short = 'v', long = "verbose", parse(from_occurrences))]
+    verbosity: usize,
+
+    /// Also print each frame in hex
+    #[clap(short = 'x', long)]
+    hex: bool,
+
+    /// Stop after this many frames
+    #[clap(short = 'c', long)]
+    count: Option<usize>,
+}
+
+pub fn run(opts: Options, format: Format) -> Result<(), Box<dyn std::error::Error>> {
+    let iface = Interface::open_default()?;
+    let layer = iface.layer();
+    let frames = iface.expect_frame(expectation::Options::forever(), |raw| {
+        Some((time::SystemTime::now(), raw.to_vec()))
+    })?;
+    eprintln!("Listening to every frame, Ctrl+C to stop");
+
+    let captured = Arc::new(AtomicUsize::new(0));
+    {
+        let captured = captured.clone();
+        ctrlc::set_handler(move || {
+            eprintln!("\n{} frames captured", captured.load(Ordering::SeqCst));
+            process::exit(0);
+        })?;
+    }
+
+    while Some(captured.load(Ordering::SeqCst)) != opts.count {
+        let (at, raw) = match frames.recv() {
+            Ok(frame) => frame,
+            Err(_) => break,
+        };
+        captured.fetch_add(1, Ordering::SeqCst);
+        let at = at.duration_since(time::UNIX_EPOCH).unwrap_or_default();
+        let summary = dissect::summary(&raw, layer, opts.verbosity >= 1);
+        let layers = if opts.verbosity >= 2 {
+            Some(dissect::dissect(&raw, layer).map_err(|e| e.to_string()))
+        } else {
+            None
+        };
+
+        match format {
+            Format::Json => {
+                let mut frame = json!({
+                    "type": "frame",
+                    "captured_at": at.as_secs_f64(),
+                    "length": raw.len(),
+                    "summary": summary,
+                });
+                match layers {
+                    Some(Ok(layers)) => frame["layers"] = json!(layers),
+                    Some(Err(message)) => frame["parse_error"] = json!(message),
+                    None => {}
+                }
+                if opts.hex {
+                    let hex: String = raw.iter().map(|b| format!("{:02x}", b)).collect();
+                    frame["hex"] = json!(hex);
+                }
+                print_json(frame, json!({}));
+            }
+            Format::Text => {
+                println!("{}.{:06} {}", at.as_secs(), at.subsec_micros(), summary);
+                match layers {
+                    Some(Ok(layers)) => print_layers(&layers),
+                    Some(Err(message)) => println!("{}", message.trim_end()),
+                    None => {}
+                }
+                if opts.hex {
+                    print_hex_dump(&raw);
+                }
+            }
+        }
+    }
+
+    eprintln!("{} frames captured", captured.load(Ordering::SeqCst));
+    Ok(())
+}
+
+/// Prints 16 bytes per line, with their offset and as ASCII.
+fn print_hex_dump(raw: &[u8]) {
+    for (i, line) in raw.chunks(16).enumerate() {
+        let hex: Vec<_> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        println!("    {:04x}:  {:<47}  {}", i * 16, hex.join(" "), ascii);
+    }
+}
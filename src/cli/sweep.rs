@@ -0,0 +1,108 @@
+use crate::cli::{
+    parse_duration,
+    print_json,
+    read_hosts,
+    Format,
+};
+use oppa::{
+    ipv4,
+    sweep::{
+        Diff,
+        Responder,
+        Sweep,
+    },
+};
+use serde_json::json;
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    time,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// Block of addresses, e.g. 10.0.0.0/24 or 10.0.0.0/255.255.255.0
+    cidr: ipv4::Cidr,
+
+    /// Echo requests sent per second
+    #[clap(long, default_value_t = 100)]
+    rate: u32,
+
+    /// How long to wait for replies after the last request
+    #[clap(short = 'w', long, parse(try_from_str = parse_duration), default_value = "1s")]
+    timeout: time::Duration,
+
+    /// Show which hosts appeared or disappeared since a sweep saved there
+    #[clap(long)]
+    compare: Option<PathBuf>,
+
+    /// Save the hosts that answered, to compare later sweeps against
+    #[clap(long)]
+    save: Option<PathBuf>,
+}
+
+pub fn run(opts: Options, format: Format) -> Result<(), Box<dyn std::error::Error>> {
+    // read it first, in case it's also where this sweep gets saved
+    let previous = match opts.compare {
+        Some(ref path) => Some(read_hosts(path)?),
+        None => None,
+    };
+
+    let sweep = Sweep::builder(opts.cidr).rate(opts.rate).timeout(opts.timeout).build()?;
+    if format == Format::Text {
+        println!("Sweeping {} ({} hosts):", opts.cidr, opts.cidr.hosts().count());
+    }
+
+    let responders = sweep.run()?;
+    let current: Vec<_> = responders.iter().map(|responder| responder.addr).collect();
+    let diff = previous.map(|previous| Diff::new(&previous, &current));
+
+    if format == Format::Json {
+        for responder in &responders {
+            print_json(responder, json!({ "type": "reply" }));
+        }
+        if let Some(ref diff) = diff {
+            for addr in &diff.appeared {
+                print_json(json!({ "type": "appeared", "addr": addr }), json!({}));
+            }
+            for addr in &diff.disappeared {
+                print_json(json!({ "type": "disappeared", "addr": addr }), json!({}));
+            }
+        }
+        print_json(json!({ "type": "summary", "cidr": opts.cidr, "hosts_up": responders.len() }), json!({}));
+    } else {
+        print_text(&responders, diff.as_ref());
+    }
+
+    if let Some(ref path) = opts.save {
+        let mut file = fs::File::create(path)?;
+        writeln!(file, "# hosts of {} that answered a sweep", opts.cidr)?;
+        for addr in &current {
+            writeln!(file, "{:?}", addr)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn print_text(responders: &[Responder], diff: Option<&Diff>) {
+    for responder in responders {
+        println!("Reply from {:?}: time={:?}", responder.addr, responder.rtt);
+    }
+    println!("{} hosts up", responders.len());
+
+    if let Some(diff) = diff {
+        if diff.is_empty() {
+            println!("\nNo changes since the last sweep");
+        } else {
+            println!("\nChanges since the last sweep:");
+            for addr in &diff.appeared {
+                println!("+ {:?}", addr);
+            }
+            for addr in &diff.disappeared {
+                println!("- {:?}", addr);
+            }
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use crate::{
+    arp,
+    icmp,
+    ipv4,
+};
+use std::{
+    cmp::Reverse,
+    collections::{
+        BinaryHeap,
+        HashMap,
+    },
+    time::Instant,
+};
+
+/// What incoming packets are looked up by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// ICMP echo replies from `peer` with this identifier and sequence
+    /// number, and ICMP errors quoting an echo request to `peer` with them.
+    Echo {
+        peer: ipv4::Addr,
+        identifier: u16,
+        sequence_number: u16,
+    },
+    /// Packets of the given protocol that a keyer registered
+    /// with `Demux::add_keyer` maps to the given value.
+    Custom(ipv4::Protocol, u64),
+}
+
+impl Key {
+    pub fn protocol(&self) -> ipv4::Protocol {
+        match *self {
+            Self::Echo { .. } => ipv4::Protocol::ICMP,
+            Self::Custom(protocol, _) => protocol,
+        }
+    }
+
+    /// The `Key::Echo` that `packet` belongs to, if any.
+    pub fn echo(packet: &ipv4::Packet) -> Option<Self> {
+        let icmp_packet = match packet.payload {
+            ipv4::Payload::ICMP(ref x) => x,
+            _ => return None,
+        };
+
+        let (peer, echo) = match (&icmp_packet.typ, &icmp_packet.header) {
+            (_, icmp::Header::EchoReply(echo)) => (packet.src, echo.clone()),
+            (icmp::Type::DestinationUnreachable(_), _) | (icmp::Type::TimeExceeded(_), _) => {
+                // errors quote the IPv4 header and the first 8 bytes
+                // of the offending packet, which is all we need.
+                let (_, quoted) = ipv4::Packet::parse(&icmp_packet.payload.0).ok()?;
+                match quoted.payload {
+                    ipv4::Payload::ICMP(icmp::Packet { header: icmp::Header::EchoRequest(echo), .. }) => {
+                        (quoted.dst, echo)
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+
+        Some(Self::Echo {
+            peer,
+            identifier: echo.identifier,
+            sequence_number: echo.sequence_number,
+        })
+    }
+}
+
+/// What a subscription is interested in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selector {
+    /// Every IPv4 packet carrying this protocol.
+    IPv4(ipv4::Protocol),
+    /// IPv4 packets matching a key.
+    Key(Key),
+    /// Every ARP packet sent to us or broadcast.
+    ARP,
+    /// Every ARP packet the link sees, whoever it's sent to.
+    AllARP,
+    /// Every frame the link delivers, as it is.
+    Frame,
+}
+
+impl Selector {
+    /// The IPv4 protocol the selected packets carry, if any.
+    fn protocol(&self) -> Option<ipv4::Protocol> {
+        match *self {
+            Self::IPv4(protocol) => Some(protocol),
+            Self::Key(key) => Some(key.protocol()),
+            Self::ARP | Self::AllARP | Self::Frame => None,
+        }
+    }
+}
+
+/// What happened to a subscription.
+#[derive(Debug, Clone)]
+pub enum Event<'a> {
+    IPv4(&'a ipv4::Packet),
+    ARP(&'a arp::Packet),
+    Frame(&'a [u8]),
+    /// The deadline passed; the subscription is removed right after.
+    Expired,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Id(u64);
+
+/// Called with every event of a subscription, returns `true` once it's done.
+pub type Callback = Box<dyn FnMut(Event) -> bool + Send>;
+
+/// Maps packets to the value of a `Key::Custom`.
+pub type Keyer = Box<dyn Fn(&ipv4::Packet) -> Option<u64> + Send>;
+
+struct Entry {
+    selector: Selector,
+    callback: Callback,
+}
+
+/// Routes incoming packets to whoever is waiting for them.
+///
+/// Keyed subscriptions are found with a hash lookup, so lots of them
+/// can be outstanding at once. The others are tried one by one
+/// against every packet they could be interested in.
+#[derive(Default)]
+pub struct Demux {
+    next_id: u64,
+    entries: HashMap<Id, Entry>,
+    by_key: HashMap<Key, Vec<Id>>,
+    unkeyed: Vec<Id>,
+    // may still hold ids that were removed before their deadline
+    deadlines: BinaryHeap<Reverse<(Instant, Id)>>,
+    keyers: Vec<(ipv4::Protocol, Keyer)>,
+    // how many entries wait for each protocol
+    protocols: Vec<(ipv4::Protocol, usize)>,
+    // how many entries want raw frames, and ARP sent to others
+    frames: usize,
+    all_arp: usize,
+}
+
+impl Demux {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Subscribes `callback` to whatever `selector` selects, until
+    /// it says it's done or `deadline` passes.
+    pub fn insert(&mut self, selector: Selector, deadline: Option<Instant>, callback: Callback) -> Id {
+        let id = Id(self.next_id);
+        self.next_id += 1;
+
+        match selector {
+            Selector::Key(key) => self.by_key.entry(key).or_default().push(id),
+            _ => self.unkeyed.push(id),
+        }
+        if let Some(protocol) = selector.protocol() {
+            match self.protocols.iter_mut().find(|(p, _)| *p == protocol) {
+                Some((_, count)) => *count += 1,
+                None => self.protocols.push((protocol, 1)),
+            }
+        }
+        match selector {
+            Selector::Frame => self.frames += 1,
+            Selector::AllARP => self.all_arp += 1,
+            _ => {}
+        }
+        if let Some(deadline) = deadline {
+            self.deadlines.push(Reverse((deadline, id)));
+        }
+
+        self.entries.insert(id, Entry { selector, callback });
+        id
+    }
+
+    /// Unsubscribes `id`, returns `false` if it was already gone.
+    pub fn remove(&mut self, id: Id) -> bool {
+        let entry = match self.entries.remove(&id) {
+            Some(x) => x,
+            None => return false,
+        };
+
+        match entry.selector {
+            Selector::Key(key) => {
+                if let Some(ids) = self.by_key.get_mut(&key) {
+                    ids.retain(|x| *x != id);
+                    if ids.is_empty() {
+                        self.by_key.remove(&key);
+                    }
+                }
+            }
+            _ => self.unkeyed.retain(|x| *x != id),
+        }
+        let protocol = entry.selector.protocol();
+        if let Some(idx) = self.protocols.iter().position(|(p, _)| Some(*p) == protocol) {
+            self.protocols[idx].1 -= 1;
+            if self.protocols[idx].1 == 0 {
+                self.protocols.remove(idx);
+            }
+        }
+        match entry.selector {
+            Selector::Frame => self.frames -= 1,
+            Selector::AllARP => self.all_arp -= 1,
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Makes `keyer` compute a `Key::Custom` for every packet carrying `protocol`.
+    pub fn add_keyer(&mut self, protocol: ipv4::Protocol, keyer: Keyer) {
+        self.keyers.push((protocol, keyer));
+    }
+
+    /// Passes `packet` to every subscription it matches, returns
+    /// whether that ended any of them. The same goes for the
+    /// other `dispatch_` functions.
+    pub fn dispatch_ipv4(&mut self, packet: &ipv4::Packet) -> bool {
+        let protocol = match packet.protocol {
+            Some(x) => x,
+            None => return false,
+        };
+
+        let mut keys = Vec::new();
+        if protocol == ipv4::Protocol::ICMP {
+            keys.extend(Key::echo(packet));
+        }
+        for (p, keyer) in self.keyers.iter() {
+            if *p == protocol {
+                keys.extend(keyer(packet).map(|k| Key::Custom(protocol, k)));
+            }
+        }
+
+        let mut ids: Vec<Id> = keys
+            .iter()
+            .filter_map(|k| self.by_key.get(k))
+            .flatten()
+            .copied()
+            .collect();
+        ids.extend(self.unkeyed_for(&[Selector::IPv4(protocol)]));
+
+        self.deliver(ids, Event::IPv4(packet))
+    }
+
+    /// Whether `packet` was sent to us is the capture filter's
+    /// business, so it goes to both kinds of ARP subscriptions.
+    pub fn dispatch_arp(&mut self, packet: &arp::Packet) -> bool {
+        let ids = self.unkeyed_for(&[Selector::ARP, Selector::AllARP]);
+        self.deliver(ids, Event::ARP(packet))
+    }
+
+    pub fn dispatch_frame(&mut self, frame: &[u8]) -> bool {
+        let ids = self.unkeyed_for(&[Selector::Frame]);
+        self.deliver(ids, Event::Frame(frame))
+    }
+
+    fn unkeyed_for(&self, selectors: &[Selector]) -> Vec<Id> {
+        self.unkeyed
+            .iter()
+            .filter(|id| selectors.contains(&self.entries[id].selector))
+            .copied()
+            .collect()
+    }
+
+    fn deliver(&mut self, ids: Vec<Id>, event: Event) -> bool {
+        let mut ended = false;
+        for id in ids {
+            let done = match self.entries.get_mut(&id) {
+                Some(entry) => (entry.callback)(event.clone()),
+                None => continue,
+            };
+            if done {
+                ended |= self.remove(id);
+            }
+        }
+        ended
+    }
+
+    /// Removes the subscriptions whose deadline is at or before `now`,
+    /// telling them so. Returns how many there were.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let mut count = 0;
+        while let Some(&Reverse((deadline, id))) = self.deadlines.peek() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.pop();
+
+            if let Some(entry) = self.entries.get_mut(&id) {
+                (entry.callback)(Event::Expired);
+                self.remove(id);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// When `expire` should be called next, if ever. Drops the
+    /// deadlines of removed entries on the way.
+    pub fn next_deadline(&mut self) -> Option<Instant> {
+        while let Some(&Reverse((deadline, id))) = self.deadlines.peek() {
+            if self.entries.contains_key(&id) {
+                return Some(deadline);
+            }
+            self.deadlines.pop();
+        }
+        None
+    }
+
+    /// The protocols subscriptions are waiting for, in no particular order.
+    pub fn protocols(&self) -> Vec<ipv4::Protocol> {
+        self.protocols.iter().map(|(p, _)| *p).collect()
+    }
+
+    /// Whether anyone is subscribed to raw frames.
+    pub fn wants_frames(&self) -> bool {
+        self.frames > 0
+    }
+
+    /// Whether anyone is subscribed to ARP sent to others.
+    pub fn wants_all_arp(&self) -> bool {
+        self.all_arp > 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::mpsc,
+        time::Duration,
+    };
+
+    fn ip(s: &str) -> ipv4::Addr {
+        s.parse().unwrap()
+    }
+
+    fn reply(identifier: u16, sequence_number: u16) -> ipv4::Packet {
+        let echo = icmp::Echo { identifier, sequence_number };
+        let payload = ipv4::Payload::ICMP(icmp::Packet::echo_reply(echo, "demux"));
+        ipv4::Packet::new(ip("10.0.0.1"), ip("10.0.0.2"), payload)
+    }
+
+    // the callback reports every event on `tx` and is done after the first one
+    fn recorder(tx: mpsc::Sender<Option<u16>>) -> Callback {
+        Box::new(move |event| {
+            let seq = match event {
+                Event::IPv4(packet) => match Key::echo(packet) {
+                    Some(Key::Echo { sequence_number, .. }) => Some(sequence_number),
+                    _ => None,
+                },
+                _ => None,
+            };
+            tx.send(seq).unwrap();
+            true
+        })
+    }
+
+    fn echo_key(identifier: u16, sequence_number: u16) -> Key {
+        Key::Echo {
+            peer: ip("10.0.0.1"),
+            identifier,
+            sequence_number,
+        }
+    }
+
+    #[test]
+    fn dispatches_by_echo_key() {
+        let (tx, rx) = mpsc::channel();
+        let mut demux = Demux::new();
+        demux.insert(Selector::Key(echo_key(1, 1)), None, recorder(tx.clone()));
+        demux.insert(Selector::Key(echo_key(1, 2)), None, recorder(tx));
+
+        assert!(demux.dispatch_ipv4(&reply(1, 2)));
+        assert!(!demux.dispatch_ipv4(&reply(2, 1)));
+        assert_eq!(rx.try_recv(), Ok(Some(2)));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(demux.len(), 1);
+
+        // the same echo from another host is someone else's
+        let mut other = reply(1, 1);
+        other.src = ip("10.0.0.3");
+        demux.dispatch_ipv4(&other);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn echo_key_of_quoted_request() {
+        let echo = icmp::Echo { identifier: 7, sequence_number: 9 };
+        let request = ipv4::Packet::new(
+            ip("10.0.0.2"),
+            ip("10.1.0.5"),
+            ipv4::Payload::ICMP(icmp::Packet::echo_request(echo, "quoted payload")),
+        );
+        let quoted = cookie_factory::gen_simple(request.serialize(), Vec::new()).unwrap();
+
+        let error = icmp::Packet {
+            typ: icmp::Type::TimeExceeded(icmp::TimeExceeded::TTLExpired),
+            checksum: 0,
+            header: icmp::Header::Other(0),
+            payload: crate::blob::Blob::new(&quoted[..20 + 8]),
+        };
+        let packet = ipv4::Packet::new(ip("10.0.0.1"), ip("10.0.0.2"), ipv4::Payload::ICMP(error));
+        let key = Key::Echo {
+            peer: ip("10.1.0.5"),
+            identifier: 7,
+            sequence_number: 9,
+        };
+        assert_eq!(Key::echo(&packet), Some(key));
+    }
+
+    #[test]
+    fn dispatches_by_custom_key() {
+        let (tx, rx) = mpsc::channel();
+        let mut demux = Demux::new();
+        demux.add_keyer(ipv4::Protocol::ICMP, Box::new(|packet| Some(packet.src.0[3] as u64)));
+        demux.insert(Selector::Key(Key::Custom(ipv4::Protocol::ICMP, 1)), None, recorder(tx));
+
+        demux.dispatch_ipv4(&reply(3, 4));
+        assert_eq!(rx.try_recv(), Ok(Some(4)));
+        assert!(demux.is_empty());
+    }
+
+    #[test]
+    fn expired_entries_are_notified_and_purged() {
+        let (tx, rx) = mpsc::channel();
+        let mut demux = Demux::new();
+        let now = Instant::now();
+        demux.insert(Selector::Key(echo_key(1, 1)), Some(now + Duration::from_secs(1)), recorder(tx.clone()));
+        demux.insert(Selector::IPv4(ipv4::Protocol::ICMP), Some(now + Duration::from_secs(2)), recorder(tx));
+        assert_eq!(demux.protocols(), vec![ipv4::Protocol::ICMP]);
+
+        assert_eq!(demux.expire(now), 0);
+        assert_eq!(demux.expire(now + Duration::from_secs(1)), 1);
+        assert_eq!(rx.try_recv(), Ok(None));
+        assert_eq!(demux.len(), 1);
+
+        assert_eq!(demux.expire(now + Duration::from_secs(5)), 1);
+        assert!(demux.is_empty());
+        assert!(demux.protocols().is_empty());
+        assert_eq!(demux.next_deadline(), None);
+    }
+
+    #[test]
+    fn counts_frame_subscriptions() {
+        let (tx, _rx) = mpsc::channel();
+        let mut demux = Demux::new();
+        let a = demux.insert(Selector::Frame, None, recorder(tx.clone()));
+        let b = demux.insert(Selector::Frame, None, recorder(tx.clone()));
+        demux.insert(Selector::ARP, None, recorder(tx));
+        assert!(demux.wants_frames());
+
+        demux.remove(a);
+        assert!(demux.wants_frames());
+        // ends the other one
+        assert!(demux.dispatch_frame(&[0; 14]));
+        assert!(!demux.wants_frames());
+        assert!(!demux.remove(b));
+        assert!(!demux.wants_frames());
+    }
+
+    #[test]
+    fn removed_entries_do_not_expire() {
+        let (tx, rx) = mpsc::channel();
+        let mut demux = Demux::new();
+        let now = Instant::now();
+        let id = demux.insert(Selector::Key(echo_key(1, 1)), Some(now), recorder(tx.clone()));
+        let later = now + Duration::from_secs(1);
+        demux.insert(Selector::Key(echo_key(1, 2)), Some(later), recorder(tx));
+
+        assert!(demux.remove(id));
+        assert!(!demux.remove(id));
+        // the removed entry no longer wakes anyone up
+        assert_eq!(demux.next_deadline(), Some(later));
+        assert_eq!(demux.expire(now), 0);
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,402 @@
+//! Field by field breakdowns of frames, for people debugging them.
+
+use crate::{
+    arp,
+    error,
+    ethernet,
+    icmp,
+    ipv4,
+    link,
+};
+use std::{
+    fmt,
+    ops::Range,
+};
+
+/// A header field and the bytes of the frame it was read from.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Field {
+    pub name: &'static str,
+    pub range: Range<usize>,
+    pub value: String,
+}
+
+/// The fields of one protocol's header, outermost first.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Layer {
+    pub name: &'static str,
+    pub range: Range<usize>,
+    pub fields: Vec<Field>,
+}
+
+/// Parses `raw`, which starts with a header of the `layer` kind, and
+/// lists the fields of every header in it. Bytes we don't know about,
+/// like Ethernet padding, end up in a last "Trailer" layer.
+/// On malformed input, the `Parse` error has the parser's hex dump.
+pub fn dissect(raw: &[u8], layer: link::Layer) -> Result<Vec<Layer>, error::Error> {
+    let mut layers = Vec::new();
+    let end = match layer {
+        link::Layer::Ethernet => {
+            let (_, frame) = ethernet::Frame::parse(raw)?;
+            layers.push(ethernet_layer(raw, &frame));
+            match frame.payload {
+                ethernet::Payload::IPv4(ref packet) => ipv4_layers(raw, 14, packet, &mut layers),
+                ethernet::Payload::ARP(ref packet) => {
+                    layers.push(arp_layer(14, packet));
+                    14 + 28
+                }
+                ethernet::Payload::Unknown => {
+                    layers.push(data_layer(raw, 14..raw.len()));
+                    raw.len()
+                }
+            }
+        }
+        link::Layer::IPv4 => {
+            let (_, packet) = ipv4::Packet::parse(raw)?;
+            ipv4_layers(raw, 0, &packet, &mut layers)
+        }
+    };
+
+    if end < raw.len() {
+        layers.push(Layer {
+            name: "Trailer",
+            range: end..raw.len(),
+            fields: vec![field("padding", end..raw.len(), hex(&raw[end..]))],
+        });
+    }
+    Ok(layers)
+}
+
+/// Describes `raw` in one line, the way tcpdump does, e.g.
+/// "02-00-00-00-00-02 > FF-FF-FF-FF-FF-FF, ARP, who-has 10.0.0.1 tell 10.0.0.2, length 42".
+/// `verbose` adds the IPv4 header fields and checksums.
+pub fn summary(raw: &[u8], layer: link::Layer, verbose: bool) -> String {
+    let mut line = String::new();
+    let ipv4 = match layer {
+        link::Layer::Ethernet => match ethernet::Frame::parse(raw) {
+            Ok((_, frame)) => {
+                line += &format!("{} > {}, ", frame.src, frame.dst);
+                match frame.payload {
+                    ethernet::Payload::IPv4(packet) => Some(packet),
+                    ethernet::Payload::ARP(ref packet) => {
+                        line += &arp_summary(packet);
+                        None
+                    }
+                    ethernet::Payload::Unknown => {
+                        line += &format!("ethertype 0x{:04x}", u16::from_be_bytes([raw[12], raw[13]]));
+                        None
+                    }
+                }
+            }
+            Err(_) => {
+                line += "malformed Ethernet frame";
+                None
+            }
+        },
+        link::Layer::IPv4 => match ipv4::Packet::parse(raw) {
+            Ok((_, packet)) => Some(packet),
+            Err(_) => {
+                line += "malformed IPv4 packet";
+                None
+            }
+        },
+    };
+
+    if let Some(packet) = ipv4 {
+        let base = match layer {
+            link::Layer::Ethernet => 14,
+            link::Layer::IPv4 => 0,
+        };
+        line += &format!("IPv4 {:?} > {:?}", packet.src, packet.dst);
+        if verbose {
+            line += &format!(
+                " (ttl {}, id 0x{:04x}{}, checksum {})",
+                packet.ttl,
+                packet.identification,
+                if packet.dont_fragment() { ", DF" } else { "" },
+                checksum_status(&raw[base..base + 20]),
+            );
+        }
+        line += ": ";
+        match packet.payload {
+            ipv4::Payload::ICMP(ref icmp) => {
+                let (typ, code) = <(u8, u8)>::from(&icmp.typ);
+                line += &format!("ICMP {:?}, type {}, code {}", icmp.typ, typ, code);
+                if let icmp::Header::EchoRequest(ref echo) | icmp::Header::EchoReply(ref echo) = icmp.header {
+                    line += &format!(", id 0x{:04x}, seq {}", echo.identifier, echo.sequence_number);
+                }
+                if verbose {
+                    let end = (base + packet.length as usize).min(raw.len());
+                    line += &format!(", checksum {}", checksum_status(&raw[(base + 20).min(end)..end]));
+                }
+            }
+            ipv4::Payload::Unknown => match packet.protocol {
+                Some(protocol) => line += &format!("{:?}", protocol),
+                None => line += &format!("protocol {}", raw[base + 9]),
+            },
+        }
+    }
+
+    line += &format!(", length {}", raw.len());
+    line
+}
+
+fn arp_summary(packet: &arp::Packet) -> String {
+    match packet.operation {
+        arp::Operation::Request => format!("ARP, who-has {:?} tell {:?}", packet.target_ip_addr, packet.sender_ip_addr),
+        arp::Operation::Reply => format!("ARP, {:?} is-at {}", packet.sender_ip_addr, packet.sender_hw_addr),
+    }
+}
+
+fn field(name: &'static str, range: Range<usize>, value: impl fmt::Display) -> Field {
+    Field {
+        name,
+        range,
+        value: value.to_string(),
+    }
+}
+
+/// Up to 32 bytes of `s` in hex, followed by how many were left out.
+fn hex(s: &[u8]) -> String {
+    let shown_len = 32;
+    let mut res: Vec<_> = s.iter().take(shown_len).map(|b| format!("{:02x}", b)).collect();
+    if s.len() > shown_len {
+        res.push(format!("+ {} bytes", s.len() - shown_len));
+    }
+    res.join(" ")
+}
+
+fn checksum_status(slice: &[u8]) -> &'static str {
+    // a correct checksum makes the whole thing sum up to zero
+    if ipv4::checksum(slice) == 0 {
+        "correct"
+    } else {
+        "incorrect"
+    }
+}
+
+fn ethernet_layer(raw: &[u8], frame: &ethernet::Frame) -> Layer {
+    let ether_type = u16::from_be_bytes([raw[12], raw[13]]);
+    let ether_type = match frame.ether_type {
+        Some(known) => format!("0x{:04x} ({:?})", ether_type, known),
+        None => format!("0x{:04x}", ether_type),
+    };
+
+    Layer {
+        name: "Ethernet",
+        range: 0..14,
+        fields: vec![
+            field("destination", 0..6, frame.dst),
+            field("source", 6..12, frame.src),
+            field("type", 12..14, ether_type),
+        ],
+    }
+}
+
+/// Pushes the IPv4 layer at `base` and the one it carries,
+/// returning where the packet ends.
+fn ipv4_layers(raw: &[u8], base: usize, packet: &ipv4::Packet, layers: &mut Vec<Layer>) -> usize {
+    let at = |range: Range<usize>| base + range.start..base + range.end;
+
+    // what the length field claims, unless it's nonsense
+    let length = packet.length as usize;
+    let end = if length >= 20 { (base + length).min(raw.len()) } else { raw.len() };
+
+    let ihl = u8::from(packet.ihl);
+    let flags = u8::from(packet.flags);
+    let flag_names: Vec<_> = [(0b010, "DF"), (0b001, "MF")]
+        .iter()
+        .filter(|&&(bit, _)| flags & bit != 0)
+        .map(|&(_, name)| name)
+        .collect();
+    let protocol = match packet.protocol {
+        Some(known) => format!("{} ({:?})", raw[base + 9], known),
+        None => raw[base + 9].to_string(),
+    };
+
+    layers.push(Layer {
+        name: "IPv4",
+        range: base..end,
+        fields: vec![
+            field("version", at(0..1), packet.version),
+            field("header length", at(0..1), format!("{} ({} bytes)", ihl, ihl as usize * 4)),
+            field("dscp", at(1..2), format!("0x{:02x}", u8::from(packet.dscp))),
+            field("ecn", at(1..2), format!("0b{:02b}", u8::from(packet.ecn))),
+            field("total length", at(2..4), packet.length),
+            field("identification", at(4..6), format!("0x{:04x}", packet.identification)),
+            field("flags", at(6..7), format!("0b{:03b} [{}]", flags, flag_names.join(" "))),
+            field("fragment offset", at(6..8), packet.fragment_offset),
+            field("ttl", at(8..9), packet.ttl),
+            field("protocol", at(9..10), protocol),
+            field(
+                "checksum",
+                at(10..12),
+                format!("0x{:04x} ({})", packet.checksum, checksum_status(&raw[base..base + 20])),
+            ),
+            field("source", at(12..16), format!("{:?}", packet.src)),
+            field("destination", at(16..20), format!("{:?}", packet.dst)),
+        ],
+    });
+
+    // like the parser, this assumes there are no IP options
+    let payload = base + 20;
+    match packet.payload {
+        ipv4::Payload::ICMP(ref icmp) => layers.push(icmp_layer(raw, payload..end, icmp)),
+        ipv4::Payload::Unknown if payload < end => layers.push(data_layer(raw, payload..end)),
+        ipv4::Payload::Unknown => {}
+    }
+    end
+}
+
+fn icmp_layer(raw: &[u8], range: Range<usize>, packet: &icmp::Packet) -> Layer {
+    let base = range.start;
+    let at = |r: Range<usize>| base + r.start..base + r.end;
+    let (typ, code) = <(u8, u8)>::from(&packet.typ);
+
+    let mut fields = vec![
+        field("type", at(0..1), format!("{} ({:?})", typ, packet.typ)),
+        field("code", at(1..2), code),
+        field(
+            "checksum",
+            at(2..4),
+            format!("0x{:04x} ({})", packet.checksum, checksum_status(&raw[range.clone()])),
+        ),
+    ];
+    match packet.header {
+        icmp::Header::EchoRequest(ref echo) | icmp::Header::EchoReply(ref echo) => {
+            fields.push(field("identifier", at(4..6), format!("0x{:04x}", echo.identifier)));
+            fields.push(field("sequence number", at(6..8), echo.sequence_number));
+        }
+        icmp::Header::Other(x) => fields.push(field("rest of header", at(4..8), format!("0x{:08x}", x))),
+    }
+    if range.end > base + 8 {
+        let data = base + 8..range.end;
+        fields.push(field("data", data.clone(), hex(&raw[data])));
+    }
+
+    Layer {
+        name: "ICMP",
+        range,
+        fields,
+    }
+}
+
+fn arp_layer(base: usize, packet: &arp::Packet) -> Layer {
+    let at = |r: Range<usize>| base + r.start..base + r.end;
+    let operation = match packet.operation {
+        arp::Operation::Request => "1 (Request)",
+        arp::Operation::Reply => "2 (Reply)",
+    };
+
+    // the parser only takes Ethernet and IPv4 addresses
+    Layer {
+        name: "ARP",
+        range: base..base + 28,
+        fields: vec![
+            field("hardware type", at(0..2), "1 (Ethernet)"),
+            field("protocol type", at(2..4), "0x0800 (IPv4)"),
+            field("hardware size", at(4..5), 6),
+            field("protocol size", at(5..6), 4),
+            field("operation", at(6..8), operation),
+            field("sender MAC", at(8..14), packet.sender_hw_addr),
+            field("sender IP", at(14..18), format!("{:?}", packet.sender_ip_addr)),
+            field("target MAC", at(18..24), packet.target_hw_addr),
+            field("target IP", at(24..28), format!("{:?}", packet.target_ip_addr)),
+        ],
+    }
+}
+
+fn data_layer(raw: &[u8], range: Range<usize>) -> Layer {
+    Layer {
+        name: "Data",
+        range: range.clone(),
+        fields: vec![field("data", range.clone(), hex(&raw[range]))],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cookie_factory as cf;
+
+    fn echo_request_frame() -> Vec<u8> {
+        let echo = icmp::Echo {
+            identifier: 0x1234,
+            sequence_number: 7,
+        };
+        let icmp = icmp::Packet::echo_request(echo, b"ping");
+        let packet = ipv4::Packet::new(ipv4::Addr([10, 0, 0, 2]), ipv4::Addr([10, 0, 0, 1]), ipv4::Payload::ICMP(icmp));
+        let frame = ethernet::Frame {
+            dst: ethernet::Addr::broadcast(),
+            src: ethernet::Addr([2, 0, 0, 0, 0, 2]),
+            ether_type: Some(ethernet::EtherType::IPv4),
+            payload: ethernet::Payload::IPv4(packet),
+        };
+        cf::gen_simple(frame.serialize(), Vec::new()).unwrap()
+    }
+
+    fn find<'a>(layer: &'a Layer, name: &str) -> &'a Field {
+        layer.fields.iter().find(|f| f.name == name).unwrap()
+    }
+
+    #[test]
+    fn dissects_an_echo_request_with_padding() {
+        let mut raw = echo_request_frame();
+        // short frames get padded up to 60 bytes on the wire
+        raw.resize(60, 0);
+
+        let layers = dissect(&raw, link::Layer::Ethernet).unwrap();
+        let names: Vec<_> = layers.iter().map(|l| l.name).collect();
+        assert_eq!(names, ["Ethernet", "IPv4", "ICMP", "Trailer"]);
+
+        assert_eq!(find(&layers[0], "type").value, "0x0800 (IPv4)");
+        assert_eq!(find(&layers[1], "destination").range, 30..34);
+        assert!(find(&layers[1], "checksum").value.ends_with("(correct)"));
+        assert_eq!(find(&layers[2], "sequence number").range, 40..42);
+        assert!(find(&layers[2], "checksum").value.ends_with("(correct)"));
+        assert_eq!(find(&layers[2], "data").value, "70 69 6e 67");
+        assert_eq!(layers[3].range, 46..60);
+    }
+
+    #[test]
+    fn summarizes_frames_in_one_line() {
+        let raw = echo_request_frame();
+        assert_eq!(
+            summary(&raw, link::Layer::Ethernet, false),
+            "02-00-00-00-00-02 > FF-FF-FF-FF-FF-FF, IPv4 10.0.0.2 > 10.0.0.1: \
+             ICMP EchoRequest, type 8, code 0, id 0x1234, seq 7, length 46"
+        );
+        assert!(summary(&raw[14..], link::Layer::IPv4, true).contains("(ttl 128, id 0x"));
+
+        let request = arp::Packet::request(ethernet::Addr([2, 0, 0, 0, 0, 2]), ipv4::Addr([10, 0, 0, 2]), ipv4::Addr([10, 0, 0, 1]));
+        let frame = ethernet::Frame {
+            dst: ethernet::Addr::broadcast(),
+            src: request.sender_hw_addr,
+            ether_type: Some(ethernet::EtherType::ARP),
+            payload: ethernet::Payload::ARP(request),
+        };
+        let raw = cf::gen_simple(frame.serialize(), Vec::new()).unwrap();
+        assert!(summary(&raw, link::Layer::Ethernet, false).ends_with("ARP, who-has 10.0.0.1 tell 10.0.0.2, length 42"));
+        assert!(summary(&raw[..20], link::Layer::Ethernet, false).starts_with("malformed"));
+    }
+
+    #[test]
+    fn flags_bad_checksums() {
+        let mut raw = echo_request_frame();
+        raw[14 + 8] = 1; // ttl
+
+        let layers = dissect(&raw, link::Layer::Ethernet).unwrap();
+        assert!(find(&layers[1], "checksum").value.ends_with("(incorrect)"));
+    }
+
+    #[test]
+    fn truncated_frames_fail_with_a_hex_dump() {
+        let raw = echo_request_frame();
+        match dissect(&raw[..30], link::Layer::Ethernet) {
+            Err(error::Error::Parse(msg)) => assert!(msg.contains("oppa parsing error"), "{}", msg),
+            res => panic!("expected a parse error, got {:?}", res.map(|layers| layers.len())),
+        }
+    }
+}
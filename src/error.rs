@@ -1,13 +1,61 @@
-use crate::netinfo;
+use crate::{
+    expectation,
+    icmp,
+    ipv4,
+    lib_loader,
+    netinfo,
+    parse,
+};
+use cookie_factory as cf;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("Raw socket error: {0}")]
-    Rawsock(#[from] rawsock::Error),
+    #[error("Packet capture error: {0}")]
+    Pcap(String),
     #[error("I/O: {0}")]
     IO(#[from] std::io::Error),
     #[error("Win32 error code {0} (0x{0:x})")]
     Win32(u32),
+    #[error("Library unavailable: {0}")]
+    LibraryUnavailable(#[from] lib_loader::BindError),
     #[error("NetInfo error: {0}")]
     NetInfo(#[from] netinfo::Error),
-}
\ No newline at end of file
+    #[error("Expectation: {0}")]
+    Expectation(#[from] expectation::RecvError),
+    #[error("The interface is closed")]
+    Closed,
+    #[error("Capture failed: {0}")]
+    CaptureFailed(String),
+    #[error("No ARP reply from {0:?}")]
+    ArpTimeout(ipv4::Addr),
+    #[error("ICMP {typ:?} from {from:?}")]
+    Icmp { from: ipv4::Addr, typ: icmp::Type },
+    #[error("The link doesn't carry Ethernet frames")]
+    NotEthernet,
+    #[error("Unsupported payload")]
+    UnsupportedPayload,
+    #[error("Serialization error: {0}")]
+    Serialize(cf::GenError),
+    #[error("Parse error: {0}")]
+    Parse(String),
+}
+
+impl From<cf::GenError> for Error {
+    fn from(e: cf::GenError) -> Self {
+        match e {
+            // that's what serializers say about payloads they don't know
+            cf::GenError::NotYetImplemented => Self::UnsupportedPayload,
+            cf::GenError::IoError(e) => Self::IO(e),
+            e => Self::Serialize(e),
+        }
+    }
+}
+
+impl From<nom::Err<parse::Error<parse::Input<'_>>>> for Error {
+    fn from(e: nom::Err<parse::Error<parse::Input<'_>>>) -> Self {
+        match e {
+            nom::Err::Error(e) | nom::Err::Failure(e) => Self::Parse(format!("{:?}", e)),
+            nom::Err::Incomplete(_) => Self::Parse("incomplete input".to_owned()),
+        }
+    }
+}
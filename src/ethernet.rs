@@ -15,11 +15,11 @@ use crate::{
     ipv4,
     arp,
 };
-use custom_debug_derive::*;
+use custom_debug_derive::Debug as CustomDebug;
 use cookie_factory as cf;
 
-#[derive(Copy, Clone, PartialEq, Eq)]
-pub struct Addr([u8; 6]);
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Addr(pub [u8; 6]);
 
 impl Addr {
     pub fn zero() -> Self {
@@ -65,7 +65,56 @@ impl fmt::Debug for Addr {
     }
 }
 
+/// As `Display` prints it.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Addr {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+/// From either form `FromStr` takes.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Addr {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseAddrError {
+    #[error("too many octets")]
+    TooManyOctets,
+    #[error("insufficient octets")]
+    InsufficientOctets,
+    #[error("invalid octet {0:?}")]
+    InvalidOctet(#[from] std::num::ParseIntError),
+}
+
+/// Parses "02-00-5E-10-00-01" as `Display` prints it, or "02:00:5e:10:00:01".
+impl std::str::FromStr for Addr {
+    type Err = ParseAddrError;
+
+    fn from_str(s: &str) -> Result<Self, ParseAddrError> {
+        let mut tokens = s.split(['-', ':']);
+
+        let mut res = Self::zero();
+        for part in res.0.iter_mut() {
+            let oct = tokens.next().ok_or(ParseAddrError::InsufficientOctets)?;
+            *part = u8::from_str_radix(oct, 16)?;
+        }
+
+        if tokens.next().is_some() {
+            return Err(ParseAddrError::TooManyOctets);
+        }
+
+        Ok(res)
+    }
+}
+
 #[derive(Debug, TryFromPrimitive, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u16)]
 pub enum EtherType {
     IPv4 = 0x0800,
@@ -89,6 +138,7 @@ impl EtherType {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Payload {
     IPv4(ipv4::Packet),
     ARP(arp::Packet),
@@ -101,12 +151,13 @@ impl Payload {
         move |out| match self {
             Self::ARP(ref packet) => tuple((EtherType::ARP.serialize(), packet.serialize()))(out),
             Self::IPv4(ref packet) => tuple((EtherType::IPv4.serialize(), packet.serialize()))(out),
-            Self::Unknown => unimplemented!(),
+            Self::Unknown => Err(cf::GenError::NotYetImplemented),
         }
     }
 }
 
 #[derive(CustomDebug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Frame {
     pub dst: Addr,
     pub src: Addr,
@@ -146,4 +197,70 @@ impl Frame {
             self.payload.serialize(),
         ))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test {
+    use super::*;
+    use crate::icmp;
+
+    fn wire(frame: &Frame) -> Vec<u8> {
+        cf::gen_simple(frame.serialize(), Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn frames_survive_a_json_round_trip() {
+        let echo = icmp::Echo {
+            identifier: 0x1234,
+            sequence_number: 7,
+        };
+        let src = ipv4::Addr([10, 0, 0, 2]);
+        let dst = ipv4::Addr([10, 0, 0, 1]);
+        let icmp = icmp::Packet::echo_request(echo, b"ping");
+        let frame = Frame {
+            dst: Addr::broadcast(),
+            src: "02:00:00:00:00:02".parse().unwrap(),
+            ether_type: Some(EtherType::IPv4),
+            payload: Payload::IPv4(ipv4::Packet::new(src, dst, ipv4::Payload::ICMP(icmp))),
+        };
+
+        let json = serde_json::to_value(&frame).unwrap();
+        assert_eq!(json["src"], "02-00-00-00-00-02");
+        assert_eq!(json["payload"]["IPv4"]["dst"], "10.0.0.1");
+        assert_eq!(json["payload"]["IPv4"]["payload"]["ICMP"]["typ"]["type"], 8);
+        assert_eq!(json["payload"]["IPv4"]["payload"]["ICMP"]["echo"]["identifier"], 0x1234);
+        assert_eq!(json["payload"]["IPv4"]["payload"]["ICMP"]["payload"], "70696e67");
+
+        let loaded: Frame = serde_json::from_value(json).unwrap();
+        assert_eq!(wire(&loaded), wire(&frame));
+    }
+
+    #[test]
+    fn frames_load_from_hand_written_json() {
+        let json = r#"{
+            "dst": "ff:ff:ff:ff:ff:ff",
+            "src": "02:00:00:00:00:02",
+            "payload": { "ARP": {
+                "operation": "Request",
+                "sender_hw_addr": "02:00:00:00:00:02",
+                "sender_ip_addr": "10.0.0.2",
+                "target_hw_addr": "00:00:00:00:00:00",
+                "target_ip_addr": "10.0.0.1"
+            } }
+        }"#;
+        let frame: Frame = serde_json::from_str(json).unwrap();
+
+        let request = arp::Packet::request(
+            "02:00:00:00:00:02".parse().unwrap(),
+            ipv4::Addr([10, 0, 0, 2]),
+            ipv4::Addr([10, 0, 0, 1]),
+        );
+        let expected = Frame {
+            dst: Addr::broadcast(),
+            src: request.sender_hw_addr,
+            ether_type: Some(EtherType::ARP),
+            payload: Payload::ARP(request),
+        };
+        assert_eq!(wire(&frame), wire(&expected));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::demux;
+use futures_core::Stream;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+    },
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+    time,
+};
+
+/// How many matching packets an expectation is interested in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Once,
+    /// `Times(0)` is over right away.
+    Times(usize),
+    /// Until the expectation is dropped or times out.
+    Forever,
+}
+
+/// How long an expectation lasts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    pub repeat: Repeat,
+    /// After this long, the expectation is over and `recv` says so.
+    pub timeout: Option<time::Duration>,
+}
+
+impl Options {
+    pub fn once() -> Self {
+        Self {
+            repeat: Repeat::Once,
+            timeout: None,
+        }
+    }
+
+    pub fn times(n: usize) -> Self {
+        Self {
+            repeat: Repeat::Times(n),
+            ..Self::once()
+        }
+    }
+
+    pub fn forever() -> Self {
+        Self {
+            repeat: Repeat::Forever,
+            ..Self::once()
+        }
+    }
+
+    pub fn timeout(self, timeout: time::Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    pub(crate) fn deadline(&self) -> Option<time::Instant> {
+        self.timeout.map(|timeout| time::Instant::now() + timeout)
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::once()
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    #[error("the expectation timed out")]
+    Expired,
+    #[error("timed out waiting for a matching packet")]
+    Timeout,
+    #[error("the expectation is over")]
+    Finished,
+}
+
+enum Message<T> {
+    Value(T),
+    Expired,
+}
+
+struct Inbox<T> {
+    queue: VecDeque<Message<T>>,
+    // set once the demux has dropped the sending side
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// Where the capture and expiry threads leave messages for an
+/// expectation, waking it up whether it's blocked or polled.
+struct Channel<T> {
+    inbox: Mutex<Inbox<T>>,
+    ready: Condvar,
+}
+
+impl<T> Channel<T> {
+    fn update(&self, f: impl FnOnce(&mut Inbox<T>)) {
+        let mut inbox = self.inbox.lock().unwrap();
+        f(&mut inbox);
+        self.ready.notify_all();
+        if let Some(waker) = inbox.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+pub(crate) struct Sender<T>(Arc<Channel<T>>);
+
+impl<T> Sender<T> {
+    fn send(&self, msg: Message<T>) {
+        self.0.update(|inbox| inbox.queue.push_back(msg));
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.0.update(|inbox| inbox.closed = true);
+    }
+}
+
+pub(crate) struct Receiver<T>(Arc<Channel<T>>);
+
+fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(Channel {
+        inbox: Mutex::new(Inbox {
+            queue: VecDeque::new(),
+            closed: false,
+            waker: None,
+        }),
+        ready: Condvar::new(),
+    });
+    (Sender(channel.clone()), Receiver(channel))
+}
+
+/// Receives whatever an expectation registered on an `Interface` matches.
+/// Dropping it unregisters the expectation.
+///
+/// It can be waited on by blocking, or used as a `Stream` that ends
+/// once the expectation is over, after yielding `Err(Expired)` if it
+/// timed out.
+pub struct Expectation<T> {
+    rx: Receiver<T>,
+    cancel: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl<T> Expectation<T> {
+    /// `cancel` is called when the expectation is dropped.
+    pub(crate) fn new(rx: Receiver<T>, cancel: Box<dyn FnOnce() + Send>) -> Self {
+        Self {
+            rx,
+            cancel: Some(cancel),
+        }
+    }
+
+    /// Blocks until the next match. Once all of them have been received,
+    /// or the interface is gone, returns `Finished`.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let channel = &self.rx.0;
+        let mut inbox = channel.inbox.lock().unwrap();
+        loop {
+            if let Some(res) = take(&mut inbox) {
+                return res;
+            }
+            inbox = channel.ready.wait(inbox).unwrap();
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: time::Duration) -> Result<T, RecvError> {
+        let deadline = time::Instant::now() + timeout;
+        let channel = &self.rx.0;
+        let mut inbox = channel.inbox.lock().unwrap();
+        loop {
+            if let Some(res) = take(&mut inbox) {
+                return res;
+            }
+            let now = time::Instant::now();
+            if now >= deadline {
+                return Err(RecvError::Timeout);
+            }
+            inbox = channel.ready.wait_timeout(inbox, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Waits for the next match without blocking the thread.
+    pub fn recv_async(&mut self) -> Recv<'_, T> {
+        Recv { expectation: self }
+    }
+
+    /// Iterates over matches until the expectation is over.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.recv().ok())
+    }
+
+    fn poll_recv(&self, cx: &mut Context) -> Poll<Result<T, RecvError>> {
+        let mut inbox = self.rx.0.inbox.lock().unwrap();
+        match take(&mut inbox) {
+            Some(res) => Poll::Ready(res),
+            None => {
+                inbox.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The next message in `inbox`, or `Finished` if there won't be any.
+fn take<T>(inbox: &mut Inbox<T>) -> Option<Result<T, RecvError>> {
+    match inbox.queue.pop_front() {
+        Some(msg) => Some(msg.into()),
+        None if inbox.closed => Some(Err(RecvError::Finished)),
+        None => None,
+    }
+}
+
+/// The future returned by `Expectation::recv_async`.
+pub struct Recv<'a, T> {
+    expectation: &'a mut Expectation<T>,
+}
+
+impl<T> Future for Recv<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.expectation.poll_recv(cx)
+    }
+}
+
+impl<T> Stream for Expectation<T> {
+    type Item = Result<T, RecvError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        match self.poll_recv(cx) {
+            Poll::Ready(Err(RecvError::Finished)) => Poll::Ready(None),
+            Poll::Ready(res) => Poll::Ready(Some(res)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T> From<Message<T>> for Result<T, RecvError> {
+    fn from(msg: Message<T>) -> Self {
+        match msg {
+            Message::Value(val) => Ok(val),
+            Message::Expired => Err(RecvError::Expired),
+        }
+    }
+}
+
+impl<T> Drop for Expectation<T> {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            cancel();
+        }
+    }
+}
+
+/// Builds a demux callback sending whatever `f` extracts from events
+/// to the returned receiver, as many times as `repeat` says.
+pub(crate) fn subscribe<T, F>(repeat: Repeat, f: F) -> (demux::Callback, Receiver<T>)
+where
+    F: Fn(&demux::Event) -> Option<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = channel();
+    let mut left = match repeat {
+        Repeat::Once => Some(1),
+        Repeat::Times(n) => Some(n),
+        Repeat::Forever => None,
+    };
+
+    let callback: demux::Callback = Box::new(move |event| {
+        let msg = match event {
+            demux::Event::Expired => Message::Expired,
+            ref event => match f(event) {
+                Some(val) => Message::Value(val),
+                None => return false,
+            },
+        };
+        tx.send(msg);
+
+        match left {
+            Some(ref mut n) => {
+                *n = n.saturating_sub(1);
+                *n == 0
+            }
+            None => false,
+        }
+    });
+
+    (callback, rx)
+}
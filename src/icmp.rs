@@ -3,7 +3,7 @@ use crate::{
     blob::Blob,
     ipv4,
 };
-use custom_debug_derive::*;
+use custom_debug_derive::Debug as CustomDebug;
 use nom::{
     number::complete::{
         be_u8,
@@ -27,6 +27,51 @@ pub enum Type {
     Other(u8, u8),
 }
 
+impl Type {
+    /// A name for the type, ignoring the code, e.g. "time_exceeded".
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Self::EchoReply => "echo_reply",
+            Self::DestinationUnreachable(_) => "destination_unreachable",
+            Self::EchoRequest => "echo_request",
+            Self::TimeExceeded(_) => "time_exceeded",
+            Self::Other(..) => "other",
+        }
+    }
+}
+
+/// As its numbers, plus its `name`: {"type": 11, "code": 0, "name": "time_exceeded"}.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Type {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let (typ, code) = <(u8, u8)>::from(self);
+        let mut st = s.serialize_struct("Type", 3)?;
+        st.serialize_field("type", &typ)?;
+        st.serialize_field("code", &code)?;
+        st.serialize_field("name", self.name())?;
+        st.end()
+    }
+}
+
+/// From its numbers; the name is ignored and the code defaults to 0.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Type {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct Numbers {
+            #[serde(rename = "type")]
+            typ: u8,
+            #[serde(default)]
+            code: u8,
+        }
+
+        let Numbers { typ, code } = Numbers::deserialize(d)?;
+        Ok(Self::from((typ, code)))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum DestinationUnreachable {
     HostUnreachable,
@@ -40,6 +85,7 @@ pub enum TimeExceeded {
 }
 
 #[derive(CustomDebug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Echo {
     #[debug(format = "{:04x}")]
     pub identifier: u16,
@@ -61,13 +107,18 @@ impl Echo {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Header {
     EchoRequest(Echo),
     EchoReply(Echo),
     Other(u32),
 }
 
+/// Through serde, the header goes by the type: an "echo" for echo
+/// requests and replies, else the raw "rest_of_header" (0 if left out).
 #[derive(CustomDebug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "PacketRepr", try_from = "PacketRepr"))]
 pub struct Packet {
     pub typ: Type,
     #[debug(skip)]
@@ -77,6 +128,57 @@ pub struct Packet {
     pub payload: Blob,
 }
 
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PacketRepr {
+    typ: Type,
+    #[serde(default)]
+    checksum: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    echo: Option<Echo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rest_of_header: Option<u32>,
+    payload: Blob,
+}
+
+#[cfg(feature = "serde")]
+impl From<Packet> for PacketRepr {
+    fn from(packet: Packet) -> Self {
+        let (echo, rest_of_header) = match packet.header {
+            Header::EchoRequest(echo) | Header::EchoReply(echo) => (Some(echo), None),
+            Header::Other(x) => (None, Some(x)),
+        };
+        Self {
+            typ: packet.typ,
+            checksum: packet.checksum,
+            echo,
+            rest_of_header,
+            payload: packet.payload,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<PacketRepr> for Packet {
+    type Error = &'static str;
+
+    fn try_from(repr: PacketRepr) -> Result<Self, Self::Error> {
+        let header = match (&repr.typ, repr.echo, repr.rest_of_header) {
+            (Type::EchoRequest, Some(echo), None) => Header::EchoRequest(echo),
+            (Type::EchoReply, Some(echo), None) => Header::EchoReply(echo),
+            (Type::EchoRequest | Type::EchoReply, _, _) => return Err("echo requests and replies take an echo"),
+            (_, None, rest_of_header) => Header::Other(rest_of_header.unwrap_or(0)),
+            (_, Some(_), _) => return Err("only echo requests and replies take an echo"),
+        };
+        Ok(Self {
+            typ: repr.typ,
+            checksum: repr.checksum,
+            header,
+            payload: repr.payload,
+        })
+    }
+}
+
 impl Echo {
     pub fn serialize<'a, W: io::Write + 'a>(&'a self) -> impl cf::SerializeFn<W> + 'a {
         use cf::{bytes::be_u16, sequence::tuple};
@@ -88,19 +190,16 @@ impl Echo {
     }
 }
 
-impl Header {
-    pub fn serialize_type_and_code<'a, W: io::Write + 'a>(&'a self) -> impl cf::SerializeFn<W> + 'a {
+impl Type {
+    pub fn serialize<'a, W: io::Write + 'a>(&'a self) -> impl cf::SerializeFn<W> + 'a {
         use cf::{bytes::be_u8, sequence::tuple};
 
-        move |out| match self {
-            Self::EchoRequest(_) => tuple((be_u8(8), be_u8(0)))(out),
-            Self::EchoReply(_) => tuple((be_u8(0), be_u8(0)))(out),
-            // we're not planning on sending any "TTL Expired" or
-            // "Host unreachable" ICMP packets.
-            _ => unimplemented!(),
-        }
+        let (typ, code) = <(u8, u8)>::from(self);
+        tuple((be_u8(typ), be_u8(code)))
     }
+}
 
+impl Header {
     pub fn serialize<'a, W: io::Write + 'a>(&'a self) -> impl cf::SerializeFn<W> + 'a {
         use cf::bytes::be_u32;
 
@@ -123,6 +222,15 @@ impl Packet {
         }
     }
 
+    pub fn echo_reply<P: AsRef<[u8]>>(echo: Echo, payload: P) -> Self {
+        Self {
+            typ: Type::EchoReply,
+            checksum: 0,
+            header: Header::EchoReply(echo),
+            payload: Blob::new(payload.as_ref()),
+        }
+    }
+
     pub fn parse(i: parse::Input) -> parse::Result<Self> {
         let (i, typ) = {
             let (i, (typ, code)) = tuple((be_u8, be_u8))(i)?;
@@ -162,7 +270,7 @@ impl Packet {
         use cf::{bytes::be_u16, sequence::tuple};
 
         tuple((
-            self.header.serialize_type_and_code(),
+            self.typ.serialize(),
             be_u16(0), // checksum
             self.header.serialize(),
             self.payload.serialize(),
@@ -182,6 +290,18 @@ impl From<(u8, u8)> for Type {
     }
 }
 
+impl From<&Type> for (u8, u8) {
+    fn from(typ: &Type) -> Self {
+        match *typ {
+            Type::EchoReply => (0, 0),
+            Type::DestinationUnreachable(ref x) => (3, x.into()),
+            Type::EchoRequest => (8, 0),
+            Type::TimeExceeded(ref x) => (11, x.into()),
+            Type::Other(typ, code) => (typ, code),
+        }
+    }
+}
+
 impl From<u8> for DestinationUnreachable {
     fn from(x: u8) -> Self {
         match x {
@@ -191,6 +311,15 @@ impl From<u8> for DestinationUnreachable {
     }
 }
 
+impl From<&DestinationUnreachable> for u8 {
+    fn from(x: &DestinationUnreachable) -> Self {
+        match *x {
+            DestinationUnreachable::HostUnreachable => 1,
+            DestinationUnreachable::Other(x) => x,
+        }
+    }
+}
+
 impl From<u8> for TimeExceeded {
     fn from(x: u8) -> Self {
         match x {
@@ -198,4 +327,50 @@ impl From<u8> for TimeExceeded {
             x => Self::Other(x),
         }
     }
-}
\ No newline at end of file
+}
+
+impl From<&TimeExceeded> for u8 {
+    fn from(x: &TimeExceeded) -> Self {
+        match *x {
+            TimeExceeded::TTLExpired => 0,
+            TimeExceeded::Other(x) => x,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn icmp_types_serialize_as_numbers() {
+        let typ = Type::from((11, 1));
+        let json = serde_json::to_value(&typ).unwrap();
+        assert_eq!(json, serde_json::json!({ "type": 11, "code": 1, "name": "time_exceeded" }));
+
+        for (typ, code) in [(0, 0), (3, 1), (3, 5), (8, 0), (11, 0), (13, 2)] {
+            let json = serde_json::to_string(&Type::from((typ, code))).unwrap();
+            let loaded: Type = serde_json::from_str(&json).unwrap();
+            assert_eq!(<(u8, u8)>::from(&loaded), (typ, code), "{}", json);
+        }
+        let loaded: Type = serde_json::from_str(r#"{"type": 8}"#).unwrap();
+        assert!(matches!(loaded, Type::EchoRequest));
+    }
+
+    #[test]
+    fn packets_take_the_header_their_type_calls_for() {
+        let json = r#"{ "typ": { "type": 0 }, "echo": { "identifier": 1, "sequence_number": 2 }, "payload": "" }"#;
+        let packet: Packet = serde_json::from_str(json).unwrap();
+        assert!(matches!(packet.header, Header::EchoReply(Echo { identifier: 1, sequence_number: 2 })));
+
+        let json = r#"{ "typ": { "type": 3, "code": 1 }, "payload": "" }"#;
+        let packet: Packet = serde_json::from_str(json).unwrap();
+        assert!(matches!(packet.header, Header::Other(0)));
+        assert_eq!(serde_json::to_value(&packet).unwrap()["rest_of_header"], 0);
+
+        let json = r#"{ "typ": { "type": 8 }, "rest_of_header": 5, "payload": "" }"#;
+        assert!(serde_json::from_str::<Packet>(json).is_err());
+        let json = r#"{ "typ": { "type": 11 }, "echo": { "identifier": 1, "sequence_number": 2 }, "payload": "" }"#;
+        assert!(serde_json::from_str::<Packet>(json).is_err());
+    }
+}
@@ -4,112 +4,310 @@ use crate::{
     ipv4,
     ethernet,
     error,
+    bpf,
+    demux::{
+        self,
+        Demux,
+    },
+    expectation::{
+        self,
+        Expectation,
+    },
+    pcap,
+    link::{
+        self,
+        Link,
+    },
 };
 use std::{
     sync::{
         mpsc,
         Arc,
+        Condvar,
         Mutex,
     },
+    thread,
     time,
 };
-use once_cell::sync::Lazy;
 
-static RAWSOCK_LIB: Lazy<Box<dyn rawsock::traits::Library>> =
-    Lazy::new(|| rawsock::open_best_library().unwrap());
+/// How long we wait for the gateway to answer our ARP request.
+const ARP_TIMEOUT: time::Duration = time::Duration::from_secs(3);
+
+/// How long the expiry thread sleeps when there are no deadlines.
+const IDLE_EXPIRY_INTERVAL: time::Duration = time::Duration::from_secs(1);
 
 struct PendingQueries {
-    ipv4: Vec<Box<dyn Fn(&ipv4::Packet) -> bool + Send>>,
+    demux: Demux,
+    // only links that carry Ethernet get a capture filter
+    filter: Option<bpf::Filter>,
+    // set once the capture thread is done
+    closed: bool,
 }
 
 impl PendingQueries {
-    pub fn new() -> Self {
+    pub fn new(filter: Option<bpf::Filter>) -> Self {
         Self {
-            ipv4: Vec::new(),
+            demux: Demux::new(),
+            filter,
+            closed: false,
+        }
+    }
+
+    /// Ends all expectations, since nothing will match them anymore.
+    fn close(&mut self) {
+        self.closed = true;
+        self.demux = Demux::new();
+    }
+
+    /// Re-installs the capture filter if the set of
+    /// protocols we're waiting for has changed.
+    fn update_filter(&mut self, link: &dyn Link) -> Result<(), error::Error> {
+        let filter = match self.filter {
+            Some(ref mut filter) => filter,
+            None => return Ok(()),
+        };
+
+        let mut protocols = self.demux.protocols();
+        protocols.sort_by_key(|p| *p as u8);
+
+        let all_arp = self.demux.wants_all_arp();
+        let accept_all = self.demux.wants_frames();
+
+        if protocols != filter.protocols || all_arp != filter.all_arp || accept_all != filter.accept_all {
+            filter.protocols = protocols;
+            filter.all_arp = all_arp;
+            filter.accept_all = accept_all;
+            link.set_filter(&filter.compile())?;
         }
+
+        Ok(())
     }
 }
 
-pub struct Interface {
-    nic: netinfo::NIC,
+/// Where outgoing IPv4 packets go on an Ethernet link.
+#[derive(Clone)]
+struct Route {
+    phy_address: ethernet::Addr,
+    gateway: ipv4::Addr,
     gateway_mac: ethernet::Addr,
-    iface: Arc<dyn rawsock::traits::DynamicInterface<'static>>,
-    pending: Arc<Mutex<PendingQueries>>,
+    subnet: Option<ipv4::Cidr>,
+}
+
+/// What the interface shares with its threads and expectations.
+struct Shared {
+    link: Arc<dyn Link>,
+    pending: Mutex<PendingQueries>,
+    // wakes up the expiry thread when deadlines change
+    expiry: Condvar,
+}
+
+impl Shared {
+    fn cancel(&self, id: demux::Id) {
+        let mut guard = self.pending.lock().unwrap();
+        if guard.demux.remove(id) {
+            let _ = guard.update_filter(self.link.as_ref());
+        }
+    }
+}
+
+/// The capture and expiry threads, stopped once the last clone
+/// of an `Interface` goes away.
+struct Threads {
+    shared: Arc<Shared>,
+    capture: Mutex<Option<thread::JoinHandle<Result<(), error::Error>>>>,
+    expiry: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl Threads {
+    /// Stops both threads and waits for them, returning the error
+    /// the capture ended with, if any. Does nothing the second time.
+    ///
+    /// Callbacks run on these threads, and may drop the last clone or
+    /// close it. They hold the expectations locked and can't wait for
+    /// themselves, so then the threads are only told to stop, and the
+    /// capture thread closes the expectations on its way out.
+    fn stop(&self) -> Result<(), error::Error> {
+        let capture = self.capture.lock().unwrap().take();
+        let expiry = self.expiry.lock().unwrap().take();
+
+        let current = Some(thread::current().id());
+        let from_inside = capture.as_ref().map(|h| h.thread().id()) == current
+            || expiry.as_ref().map(|h| h.thread().id()) == current;
+
+        self.shared.link.break_loop();
+        if !from_inside {
+            self.shared.pending.lock().unwrap().close();
+        }
+        self.shared.expiry.notify_all();
+        if from_inside {
+            return Ok(());
+        }
+
+        if let Some(expiry) = expiry {
+            expiry.join().unwrap_or(());
+        }
+        match capture {
+            Some(capture) => capture.join().unwrap_or_else(|_| Err(thread_panicked())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Threads {
+    fn drop(&mut self) {
+        // there's nobody left to report errors to
+        let _ = self.stop();
+    }
+}
+
+/// A handle to a network interface. Clones share the same link,
+/// capture thread and expectations, and can be used from any thread.
+/// Capture stops when the last clone is dropped, or on `close`.
+#[derive(Clone)]
+pub struct Interface {
+    address: ipv4::Addr,
+    // `None` for links that carry bare IPv4 packets
+    route: Option<Route>,
+    shared: Arc<Shared>,
+    threads: Arc<Threads>,
 }
 
 impl Interface {
+    /// Captures on the interface of the default route, through Npcap
+    /// on Windows and libpcap on Linux, where it needs `CAP_NET_RAW`.
+    /// Other platforms get `netinfo::Error::Unsupported`.
     pub fn open_default() -> Result<Self, error::Error> {
         let nic = netinfo::default_nic()?;
-        let iface_name = format!(r#"\Device\NPF_{}"#, nic.guid);
-        let iface = RAWSOCK_LIB.open_interface_arc(&iface_name)?;
-
-        let pending = Arc::new(Mutex::new(PendingQueries::new()));
-
-        let gateway_mac = crossbeam_utils::thread::scope(|s| {
-            let (tx, rx) = mpsc::channel();
-            let gateway_ip = nic.gateway;
-
-            let poll_iface = iface.clone();
-            s.spawn(move |_| {
-                poll_iface.loop_infinite_dyn(&mut |packet| {
-                    let frame = match ethernet::Frame::parse(packet) {
-                        Ok((_remaining, frame)) => frame,
-                        _ => return,
-                    };
-                    let arp = match frame.payload {
-                        ethernet::Payload::ARP(x) => x,
-                        _ => return,
-                    };
-                    if let arp::Operation::Reply = arp.operation {
-                        if arp.sender_ip_addr == gateway_ip {
-                            tx.send(arp.sender_hw_addr).unwrap();
-                        }
-                    }
-                })
-                .unwrap();
-            });
-
-            let frame = ethernet::Frame {
-                src: nic.phy_address,
-                dst: ethernet::Addr::broadcast(),
-                ether_type: Some(ethernet::EtherType::ARP),
-                payload: ethernet::Payload::ARP(arp::Packet::request(&nic)),
-            };
-            send_ethernet(iface.as_ref(), frame).unwrap();
-
-            let ret = rx.recv_timeout(time::Duration::from_secs(3))
-                        .map_err(|_| "ARP timeout")
-                        .unwrap();
-            iface.break_loop();
-            ret
-        })
-        .unwrap();
+        let capture = pcap::Capture::open(&nic.name)?;
 
-        let res = Self {
-            nic,
+        Self::open_ethernet(Arc::new(capture), nic)
+    }
+
+    /// Opens an interface over any link carrying Ethernet frames,
+    /// e.g. a port of a simulated network.
+    pub fn open_ethernet(link: Arc<dyn Link>, nic: netinfo::NIC) -> Result<Self, error::Error> {
+        let filter = bpf::Filter {
+            mac: nic.phy_address,
+            ip: nic.address,
+            protocols: Vec::new(),
+            all_arp: false,
+            accept_all: false,
+        };
+        link.set_filter(&filter.compile())?;
+
+        let gateway_mac = resolve_gateway(link.as_ref(), &nic)?;
+
+        let route = Route {
+            phy_address: nic.phy_address,
+            gateway: nic.gateway,
             gateway_mac,
-            iface: iface.clone(),
-            pending: pending.clone(),
+            subnet: nic.subnet(),
         };
 
-        std::thread::spawn(move || {
-            iface.loop_infinite_dyn(&mut |packet| {
-                let frame = match ethernet::Frame::parse(packet) {
-                    Ok((_, frame)) => frame,
-                    _ => return,
-                };
+        Ok(Self::start(link, nic.address, Some(route), Some(filter)))
+    }
 
-                if let ethernet::Payload::IPv4(ref packet) = frame.payload {
-                    let mut guard = pending.lock().unwrap();
-                    if let Some(idx) = guard.ipv4.iter().position(|f| f(packet)) {
-                        let _rem = guard.ipv4.remove(idx);
-                    }
-                }
-            })
-            .unwrap();
+    /// Opens an interface over an ICMP socket, which ordinary users may do
+    /// when `net.ipv4.ping_group_range` allows it. Only ICMP can be sent
+    /// and expected through it.
+    #[cfg(target_os = "linux")]
+    pub fn open_icmp_socket() -> Result<Self, error::Error> {
+        let socket = link::socket::IcmpSocket::open()?;
+        // the kernel picks our source address
+        Ok(Self::start(Arc::new(socket), ipv4::Addr::zero(), None, None))
+    }
+
+    /// Opens an interface over the TUN device `name`, sending
+    /// bare IPv4 packets from `address`.
+    #[cfg(target_os = "linux")]
+    pub fn open_tun(name: &str, address: ipv4::Addr) -> Result<Self, error::Error> {
+        let tun = link::tun::Tun::open(name)?;
+        Ok(Self::start(Arc::new(tun), address, None, None))
+    }
+
+    fn start(
+        link: Arc<dyn Link>,
+        address: ipv4::Addr,
+        route: Option<Route>,
+        filter: Option<bpf::Filter>,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            link: link.clone(),
+            pending: Mutex::new(PendingQueries::new(filter)),
+            expiry: Condvar::new(),
         });
 
-        Ok(res)
+        let expiry = {
+            let shared = shared.clone();
+            thread::spawn(move || expire_queries(&shared))
+        };
+
+        let capture = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let res = capture(&shared);
+                // wake up whoever is still waiting
+                shared.pending.lock().unwrap().close();
+                shared.expiry.notify_all();
+                res
+            })
+        };
+
+        Self {
+            address,
+            route,
+            shared: shared.clone(),
+            threads: Arc::new(Threads {
+                shared,
+                capture: Mutex::new(Some(capture)),
+                expiry: Mutex::new(Some(expiry)),
+            }),
+        }
+    }
+
+    /// Stops capturing and waits for the capture thread, returning the
+    /// error it stopped with, if any. This closes all clones too: their
+    /// expectations end, and registering new ones fails with `Closed`.
+    pub fn close(self) -> Result<(), error::Error> {
+        self.threads.stop()
+    }
+
+    /// The address packets are sent from. Zero when the OS picks it.
+    pub fn address(&self) -> ipv4::Addr {
+        self.address
+    }
+
+    /// Our MAC address, or `None` for links that carry bare IPv4 packets.
+    pub fn phy_address(&self) -> Option<ethernet::Addr> {
+        self.route.as_ref().map(|route| route.phy_address)
+    }
+
+    /// The address of the gateway of an Ethernet link.
+    pub fn gateway(&self) -> Option<ipv4::Addr> {
+        self.route.as_ref().map(|route| route.gateway)
+    }
+
+    /// The subnet an Ethernet link is attached to, if known.
+    pub fn subnet(&self) -> Option<ipv4::Cidr> {
+        self.route.as_ref().and_then(|route| route.subnet)
+    }
+
+    /// What the frames of the link start with.
+    pub fn layer(&self) -> link::Layer {
+        self.shared.link.layer()
+    }
+
+    /// Sends `packet` in a frame to `dst`, e.g. `ethernet::Addr::broadcast()`.
+    /// Fails with `NotEthernet` on links that carry bare IPv4 packets.
+    pub fn send_arp(&self, packet: arp::Packet, dst: ethernet::Addr) -> Result<(), error::Error> {
+        let route = self.route.as_ref().ok_or(error::Error::NotEthernet)?;
+        let frame = ethernet::Frame {
+            src: route.phy_address,
+            dst,
+            ether_type: Some(ethernet::EtherType::ARP),
+            payload: ethernet::Payload::ARP(packet),
+        };
+        send_ethernet(self.shared.link.as_ref(), frame)
     }
 
     pub fn send_ipv4(
@@ -117,50 +315,465 @@ impl Interface {
         payload: ipv4::Payload,
         addr: &ipv4::Addr,
     ) -> Result<(), error::Error> {
-        let frame = ethernet::Frame {
-            src: self.nic.phy_address,
-            dst: self.gateway_mac,
-            ether_type: Some(ethernet::EtherType::IPv4),
-            payload: ethernet::Payload::IPv4(
-                ipv4::Packet::new(
-                    self.nic.address,
-                    addr.clone(),
-                    payload,
-            )),
-        };
+        self.send_ipv4_packet(ipv4::Packet::new(self.address, *addr, payload))
+    }
 
-       send_ethernet(self.iface.as_ref(), frame)
+    /// Sends `packet` as it is, for when the defaults
+    /// of `send_ipv4` (e.g. the TTL) don't do.
+    pub fn send_ipv4_packet(&self, packet: ipv4::Packet) -> Result<(), error::Error> {
+        match self.route {
+            Some(ref route) => {
+                let frame = ethernet::Frame {
+                    src: route.phy_address,
+                    dst: route.gateway_mac,
+                    ether_type: Some(ethernet::EtherType::IPv4),
+                    payload: ethernet::Payload::IPv4(packet),
+                };
+                send_ethernet(self.shared.link.as_ref(), frame)
+            }
+            None => {
+                let serialized = cookie_factory::gen_simple(packet.serialize(), Vec::new())?;
+                self.shared.link.send(&serialized)
+            }
+        }
     }
 
-    pub fn expect_ipv4<F, T>(&mut self, f: F) -> mpsc::Receiver<T>
+    /// Registers `f` to be called on every incoming IPv4 packet
+    /// carrying `protocol`. Whatever it returns `Some` of goes
+    /// to the expectation, for as long as `options` says.
+    ///
+    /// `f` runs on the capture thread while it holds the interface's
+    /// expectations locked, so it must not register, drop or wait on
+    /// expectations: any of those deadlocks. Sending and closing are
+    /// fine. The same goes for the other `expect_` functions.
+    pub fn expect_ipv4<F, T>(
+        &self,
+        protocol: ipv4::Protocol,
+        options: expectation::Options,
+        f: F,
+    ) -> Result<Expectation<T>, error::Error>
     where
         F: Fn(&ipv4::Packet) -> Option<T> + Send + 'static,
         T: Send + 'static,
     {
-        let (tx, rx) = mpsc::channel();
+        self.expect(demux::Selector::IPv4(protocol), options, move |event| match *event {
+            demux::Event::IPv4(packet) => f(packet),
+            _ => None,
+        })
+    }
 
-        let mut guard = self.pending.lock().unwrap();
-        guard.ipv4.push(Box::new(move |packet| {
-            match f(&packet) {
-                Some(val) => {
-                    tx.send(val).unwrap_or(()); // ignore send errors
-                    true
-                }
-                None => false,
+    /// Like `expect_ipv4`, but only looks at packets matching `key`.
+    pub fn expect_keyed<F, T>(
+        &self,
+        key: demux::Key,
+        options: expectation::Options,
+        f: F,
+    ) -> Result<Expectation<T>, error::Error>
+    where
+        F: Fn(&ipv4::Packet) -> Option<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.expect(demux::Selector::Key(key), options, move |event| match *event {
+            demux::Event::IPv4(packet) => f(packet),
+            _ => None,
+        })
+    }
+
+    /// Registers `f` to be called on every incoming ARP packet sent
+    /// to us or broadcast. Links that carry bare IPv4 packets never
+    /// see any.
+    pub fn expect_arp<F, T>(
+        &self,
+        options: expectation::Options,
+        f: F,
+    ) -> Result<Expectation<T>, error::Error>
+    where
+        F: Fn(&arp::Packet) -> Option<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.expect(demux::Selector::ARP, options, move |event| match *event {
+            demux::Event::ARP(packet) => f(packet),
+            _ => None,
+        })
+    }
+
+    /// Like `expect_arp`, but also sees ARP between other stations,
+    /// which the capture filter lets through while the expectation
+    /// lasts. On a switched network, most of it only reaches a
+    /// mirror port.
+    pub fn expect_all_arp<F, T>(
+        &self,
+        options: expectation::Options,
+        f: F,
+    ) -> Result<Expectation<T>, error::Error>
+    where
+        F: Fn(&arp::Packet) -> Option<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.expect(demux::Selector::AllARP, options, move |event| match *event {
+            demux::Event::ARP(packet) => f(packet),
+            _ => None,
+        })
+    }
+
+    /// Registers `f` to be called on every frame the link receives,
+    /// before it's parsed. This lifts the capture filter while the
+    /// expectation lasts.
+    pub fn expect_frame<F, T>(
+        &self,
+        options: expectation::Options,
+        f: F,
+    ) -> Result<Expectation<T>, error::Error>
+    where
+        F: Fn(&[u8]) -> Option<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.expect(demux::Selector::Frame, options, move |event| match *event {
+            demux::Event::Frame(frame) => f(frame),
+            _ => None,
+        })
+    }
+
+    fn expect<F, T>(
+        &self,
+        selector: demux::Selector,
+        options: expectation::Options,
+        f: F,
+    ) -> Result<Expectation<T>, error::Error>
+    where
+        F: Fn(&demux::Event) -> Option<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (callback, rx) = expectation::subscribe(options.repeat, f);
+
+        let mut guard = self.shared.pending.lock().unwrap();
+        if guard.closed {
+            return Err(error::Error::Closed);
+        }
+        // wanting no matches at all is over before it starts
+        if options.repeat == expectation::Repeat::Times(0) {
+            drop(callback);
+            return Ok(Expectation::new(rx, Box::new(|| {})));
+        }
+        let id = guard.demux.insert(selector, options.deadline(), callback);
+        if let Err(e) = guard.update_filter(self.shared.link.as_ref()) {
+            guard.demux.remove(id);
+            return Err(e);
+        }
+        drop(guard);
+        self.shared.expiry.notify_one();
+
+        let shared = Arc::downgrade(&self.shared);
+        Ok(Expectation::new(rx, Box::new(move || {
+            if let Some(shared) = shared.upgrade() {
+                shared.cancel(id);
             }
-        }));
+        })))
+    }
 
-        rx
+    /// Lets `expect_keyed` look up packets carrying `protocol`
+    /// by `Key::Custom(protocol, keyer(packet))`. `keyer` runs
+    /// like the callbacks of `expect_ipv4` do.
+    pub fn add_keyer<F>(&self, protocol: ipv4::Protocol, keyer: F)
+    where
+        F: Fn(&ipv4::Packet) -> Option<u64> + Send + 'static,
+    {
+        let mut guard = self.shared.pending.lock().unwrap();
+        guard.demux.add_keyer(protocol, Box::new(keyer));
     }
 }
 
+/// Passes everything the link receives to the demux, until it's told to stop.
+fn capture(shared: &Shared) -> Result<(), error::Error> {
+    let link = shared.link.as_ref();
+    let layer = link.layer();
+    link.loop_infinite(&mut |raw| {
+        let payload = match layer {
+            link::Layer::Ethernet => match ethernet::Frame::parse(raw) {
+                Ok((_, frame)) => frame.payload,
+                _ => ethernet::Payload::Unknown,
+            },
+            link::Layer::IPv4 => match ipv4::Packet::parse(raw) {
+                Ok((_, packet)) => ethernet::Payload::IPv4(packet),
+                _ => ethernet::Payload::Unknown,
+            },
+        };
+
+        let mut guard = shared.pending.lock().unwrap();
+        let mut ended = guard.demux.wants_frames() && guard.demux.dispatch_frame(raw);
+        ended |= match payload {
+            ethernet::Payload::IPv4(ref packet) => guard.demux.dispatch_ipv4(packet),
+            ethernet::Payload::ARP(ref packet) => guard.demux.dispatch_arp(packet),
+            ethernet::Payload::Unknown => false,
+        };
+        // the filter only needs a look once subscriptions are gone. a stale
+        // one lets through a few extra packets, so failing here is not fatal.
+        if ended {
+            let _ = guard.update_filter(link);
+        }
+    })
+}
+
+/// Purges expired queries until the interface is closed.
+fn expire_queries(shared: &Shared) {
+    let mut guard = shared.pending.lock().unwrap();
+    while !guard.closed {
+        let now = time::Instant::now();
+        if guard.demux.expire(now) > 0 {
+            let _ = guard.update_filter(shared.link.as_ref());
+        }
+
+        let timeout = guard
+            .demux
+            .next_deadline()
+            .map(|deadline| deadline.saturating_duration_since(now))
+            .unwrap_or(IDLE_EXPIRY_INTERVAL)
+            .min(IDLE_EXPIRY_INTERVAL);
+        guard = shared.expiry.wait_timeout(guard, timeout).unwrap().0;
+    }
+}
+
+/// Asks for the MAC address of `nic`'s gateway, before
+/// there's a capture thread to hear the answer for us.
+fn resolve_gateway(link: &dyn Link, nic: &netinfo::NIC) -> Result<ethernet::Addr, error::Error> {
+    let gateway_ip = nic.gateway;
+    let (tx, rx) = mpsc::channel();
+
+    crossbeam_utils::thread::scope(|s| {
+        let capture = s.spawn(move |_| {
+            link.loop_infinite(&mut |packet| {
+                let frame = match ethernet::Frame::parse(packet) {
+                    Ok((_remaining, frame)) => frame,
+                    _ => return,
+                };
+                let arp = match frame.payload {
+                    ethernet::Payload::ARP(x) => x,
+                    _ => return,
+                };
+                if let arp::Operation::Reply = arp.operation {
+                    if arp.sender_ip_addr == gateway_ip {
+                        tx.send(arp.sender_hw_addr).unwrap_or(()); // ignore duplicate replies
+                    }
+                }
+            })
+        });
+
+        let frame = ethernet::Frame {
+            src: nic.phy_address,
+            dst: ethernet::Addr::broadcast(),
+            ether_type: Some(ethernet::EtherType::ARP),
+            payload: ethernet::Payload::ARP(arp::Packet::request(nic.phy_address, nic.address, nic.gateway)),
+        };
+        let res = send_ethernet(link, frame).and_then(|_| {
+            rx.recv_timeout(ARP_TIMEOUT)
+                .map_err(|_| error::Error::ArpTimeout(gateway_ip))
+        });
+        link.break_loop();
+
+        // if capturing failed, that's why there was no reply
+        capture.join().unwrap_or_else(|_| Err(thread_panicked()))?;
+        res
+    })
+    .unwrap_or_else(|_| Err(thread_panicked()))
+}
+
+fn thread_panicked() -> error::Error {
+    error::Error::CaptureFailed("the capture thread panicked".to_owned())
+}
+
 fn send_ethernet(
-    iface: &dyn rawsock::traits::DynamicInterface,
+    link: &dyn Link,
     frame: ethernet::Frame,
 ) -> Result<(), error::Error> {
-    let serialized = cookie_factory::gen_simple(frame.serialize(), Vec::new()).unwrap();
-    iface.send(&serialized)?;
-    iface.flush();
+    let serialized = cookie_factory::gen_simple(frame.serialize(), Vec::new())?;
+    link.send(&serialized)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        expectation::{
+            Options,
+            RecvError,
+        },
+        icmp,
+        sim::{
+            fixture::*,
+            Conditions,
+            Host,
+            Network,
+        },
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn clones_ping_concurrently() {
+        fn assert_shareable<T: Send + Sync + Clone>() {}
+        assert_shareable::<Interface>();
+
+        let (_net, _wan, iface) = routed();
+
+        let threads: Vec<_> = (0..8)
+            .map(|sequence_number| {
+                let iface = iface.clone();
+                thread::spawn(move || ping(&iface, ip("10.1.0.5"), sequence_number))
+            })
+            .collect();
+        for t in threads {
+            assert_eq!(t.join().unwrap().unwrap().src, ip("10.1.0.5"));
+        }
+    }
+
+    #[test]
+    fn unanswered_keyed_expectation_expires() {
+        let net = Network::new(0);
+        let lan = net.segment(Conditions::default());
+        net.host(lan, Host { echo: false, ..Host::new(mac(1), ip("10.0.0.1")) });
+        let iface = station(&net, lan, 2);
+
+        let key = demux::Key::Echo {
+            peer: ip("10.0.0.1"),
+            identifier: 1,
+            sequence_number: 1,
+        };
+        let rx = iface
+            .expect_keyed(key, Options::once().timeout(Duration::from_millis(100)), is_echo_reply)
+            .unwrap();
+
+        assert_eq!(rx.recv_timeout(TIMEOUT).map(|_| ()), Err(RecvError::Expired));
+        assert_eq!(rx.recv_timeout(TIMEOUT).map(|_| ()), Err(RecvError::Finished));
+    }
+
+    #[test]
+    fn multi_shot_expectation_collects_duplicates() {
+        let (_net, _lan, iface) = lan_with(Conditions { duplication: 1.0, ..Default::default() });
+
+        let key = demux::Key::Echo {
+            peer: ip("10.0.0.1"),
+            identifier: 1,
+            sequence_number: 1,
+        };
+        let rx = iface
+            .expect_keyed(key, Options::times(2).timeout(TIMEOUT), is_echo_reply)
+            .unwrap();
+        let echo = icmp::Echo {
+            identifier: 1,
+            sequence_number: 1,
+        };
+        let payload = ipv4::Payload::ICMP(icmp::Packet::echo_request(echo, "dup"));
+        iface.send_ipv4(payload, &ip("10.0.0.1")).unwrap();
+
+        // the request and the replies are all doubled, but we asked for two
+        assert_eq!(rx.iter().count(), 2);
+    }
+
+    #[test]
+    fn expecting_no_matches_is_over_right_away() {
+        let (_net, _lan, iface) = lan();
+        let rx = iface.expect_frame(Options::times(0), |frame| Some(frame.len())).unwrap();
+        iface.send_arp(arp::Packet::announcement(mac(2), ip("10.0.0.2")), ethernet::Addr::broadcast()).unwrap();
+        assert_eq!(rx.recv(), Err(RecvError::Finished));
+    }
+
+    #[test]
+    fn arp_and_frame_expectations() {
+        let net = Network::new(0);
+        let lan = net.segment(Conditions::default());
+        net.host(lan, Host::new(mac(1), ip("10.0.0.1")));
+        net.host(lan, Host::new(mac(3), ip("10.0.0.3")));
+        let port = Arc::new(net.port(lan, mac(2), ip("10.0.0.2")));
+
+        let iface = Interface::open_ethernet(port.clone(), nic("10.0.0.2", 2, "10.0.0.1")).unwrap();
+        let arp_rx = iface
+            .expect_arp(Options::forever().timeout(Duration::from_millis(200)), |packet| {
+                Some(packet.sender_ip_addr)
+            })
+            .unwrap();
+        let frame_rx = iface
+            .expect_frame(Options::times(2), |frame| Some(frame.len()))
+            .unwrap();
+
+        link::Link::send(port.as_ref(), &arp_request(&nic("10.0.0.2", 2, "10.0.0.3"))).unwrap();
+        link::Link::send(port.as_ref(), &arp_request(&nic("10.0.0.2", 2, "10.0.0.1"))).unwrap();
+
+        let mut senders: Vec<_> = arp_rx.iter().collect();
+        senders.sort_by_key(|addr| addr.0);
+        assert_eq!(senders, vec![ip("10.0.0.1"), ip("10.0.0.3")]);
+        assert_eq!(frame_rx.iter().count(), 2);
+    }
+
+    #[test]
+    fn close_ends_expectations_of_all_clones() {
+        let (_net, _lan, iface) = lan();
+
+        let clone = iface.clone();
+        let rx = clone.expect_arp(Options::forever(), |_| Some(())).unwrap();
+
+        iface.close().unwrap();
+        assert_eq!(rx.recv_timeout(TIMEOUT), Err(RecvError::Finished));
+        assert!(matches!(clone.expect_arp(Options::once(), |_| Some(())), Err(error::Error::Closed)));
+    }
+
+    #[test]
+    fn callbacks_can_close_the_interface() {
+        let (_net, _lan, iface) = lan();
+
+        // the capture thread closes the interface, and can't wait for itself
+        let clone = Mutex::new(Some(iface.clone()));
+        let rx = iface
+            .expect_frame(Options::forever(), move |_| {
+                let closed = clone.lock().unwrap().take().map(Interface::close);
+                closed.map(|res| res.is_ok())
+            })
+            .unwrap();
+        let echo = icmp::Echo {
+            identifier: 1,
+            sequence_number: 1,
+        };
+        let payload = ipv4::Payload::ICMP(icmp::Packet::echo_request(echo, "close"));
+        iface.send_ipv4(payload, &ip("10.0.0.1")).unwrap();
 
-    Ok(())
-}
\ No newline at end of file
+        assert_eq!(rx.recv_timeout(TIMEOUT), Ok(true));
+        assert_eq!(rx.recv_timeout(TIMEOUT), Err(RecvError::Finished));
+        assert!(matches!(iface.expect_arp(Options::once(), |_| Some(())), Err(error::Error::Closed)));
+    }
+
+    #[test]
+    fn dropping_last_clone_releases_link() {
+        let net = Network::new(0);
+        let lan = net.segment(Conditions::default());
+        net.host(lan, Host::new(mac(1), ip("10.0.0.1")));
+
+        for _ in 0..3 {
+            let port = Arc::new(net.port(lan, mac(2), ip("10.0.0.2")));
+            let weak = Arc::downgrade(&port);
+
+            let iface = Interface::open_ethernet(port, nic("10.0.0.2", 2, "10.0.0.1")).unwrap();
+            let clone = iface.clone();
+            let _rx = clone.expect_frame(Options::forever(), |_| Some(())).unwrap();
+            drop(iface);
+            assert!(weak.upgrade().is_some());
+            drop(clone);
+            assert!(weak.upgrade().is_none());
+        }
+    }
+
+    #[test]
+    fn missing_gateway_is_an_error() {
+        let net = Network::new(0);
+        let lan = net.segment(Conditions::default());
+        let port = net.port(lan, mac(2), ip("10.0.0.2"));
+
+        let res = Interface::open_ethernet(Arc::new(port), nic("10.0.0.2", 2, "10.0.0.1"));
+        assert!(matches!(res, Err(error::Error::ArpTimeout(addr)) if addr == ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn sending_unknown_payload_is_an_error() {
+        let (_net, _wan, iface) = routed();
+
+        let res = iface.send_ipv4(ipv4::Payload::Unknown, &ip("10.1.0.5"));
+        assert!(matches!(res, Err(error::Error::UnsupportedPayload)));
+    }
+}
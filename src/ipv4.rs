@@ -3,7 +3,7 @@ use std::{
     io,
 };
 use derive_try_from_primitive::*;
-use custom_debug_derive::*;
+use custom_debug_derive::Debug as CustomDebug;
 use nom::{
     bytes::complete::take,
     error::context,
@@ -24,10 +24,11 @@ use crate::{
 };
 use cookie_factory as cf;
 
-#[derive(PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub struct Addr(pub [u8; 4]);
 
-#[derive(Debug, TryFromPrimitive, Copy, Clone)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum Protocol {
     ICMP = 0x01,
@@ -36,28 +37,42 @@ pub enum Protocol {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Payload {
     ICMP(icmp::Packet),
     Unknown,
 }
 
+// the middle of the three flag bits
+const DONT_FRAGMENT: u8 = 0b010;
+
+/// Through serde, missing fields take their `Default` value, and the
+/// small header fields are plain integers.
 #[derive(CustomDebug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Packet {
     #[debug(skip)]
+    #[cfg_attr(feature = "serde", serde(with = "ux_int"))]
     pub version: ux::u4,
     #[debug(format = "{}")]
+    #[cfg_attr(feature = "serde", serde(with = "ux_int"))]
     pub ihl: ux::u4,
     #[debug(format = "{:x}")]
+    #[cfg_attr(feature = "serde", serde(with = "ux_int"))]
     pub dscp: ux::u6,
     #[debug(format = "{:b}")]
+    #[cfg_attr(feature = "serde", serde(with = "ux_int"))]
     pub ecn: ux::u2,
     pub length: u16,
 
     #[debug(format = "{:04x}")]
     pub identification: u16,
     #[debug(format = "{:b}")]
+    #[cfg_attr(feature = "serde", serde(with = "ux_int"))]
     pub flags: ux::u3,
     #[debug(format = "{}")]
+    #[cfg_attr(feature = "serde", serde(with = "ux_int"))]
     pub fragment_offset: ux::u13,
 
     #[debug(format = "{}")]
@@ -73,6 +88,38 @@ pub struct Packet {
     pub payload: Payload,
 }
 
+/// (De)serializes the `ux` integers of the header as plain numbers.
+#[cfg(feature = "serde")]
+mod ux_int {
+    use serde::{
+        de::{
+            Error,
+            Unexpected,
+        },
+        Deserialize,
+        Deserializer,
+        Serializer,
+    };
+
+    pub fn serialize<T, S>(x: &T, s: S) -> Result<S::Ok, S::Error>
+    where
+        T: Copy,
+        u16: From<T>,
+        S: Serializer,
+    {
+        s.serialize_u16(u16::from(*x))
+    }
+
+    pub fn deserialize<'de, T, D>(d: D) -> Result<T, D::Error>
+    where
+        T: TryFrom<u16>,
+        D: Deserializer<'de>,
+    {
+        let x = u16::deserialize(d)?;
+        T::try_from(x).map_err(|_| D::Error::invalid_value(Unexpected::Unsigned(x.into()), &"a narrower integer"))
+    }
+}
+
 impl Protocol {
     pub fn parse(i: parse::Input) -> parse::Result<Option<Self>> {
         let (i, x) = context("IPv4 Protocol", be_u8)(i)?;
@@ -91,7 +138,7 @@ impl Protocol {
 
 impl Addr {
     pub fn zero() -> Self {
-        return Self([0, 0, 0, 0])
+        Self([0, 0, 0, 0])
     }
 
     pub fn parse(i: parse::Input) -> parse::Result<Self> {
@@ -108,17 +155,19 @@ impl Addr {
 }
 
 impl Payload {
-    pub fn protocol(&self) -> Protocol {
+    /// `None` for payloads we can't tell the protocol of.
+    pub fn protocol(&self) -> Option<Protocol> {
         match self {
-            Self::ICMP(_) => Protocol::ICMP,
-            _ => unimplemented!(),
+            Self::ICMP(_) => Some(Protocol::ICMP),
+            _ => None,
         }
     }
 
+    /// Fails with `NotYetImplemented` for payloads we don't know how to write.
     pub fn serialize<'a, W: io::Write + 'a>(&'a self) -> impl cf::SerializeFn<W> + 'a {
         move |out| match self {
             Self::ICMP(ref icmp) => icmp.serialize()(out),
-            _ => unimplemented!(),
+            _ => Err(cf::GenError::NotYetImplemented),
         }
     }
 }
@@ -126,7 +175,7 @@ impl Payload {
 impl Packet {
     pub fn new(src: Addr, dst: Addr, p: Payload) -> Self {
         Self {
-            protocol: Some(p.protocol()),
+            protocol: p.protocol(),
             payload: p,
             src,
             dst,
@@ -134,6 +183,25 @@ impl Packet {
         }
     }
 
+    /// The type of service byte, i.e. the DSCP and ECN fields together.
+    pub fn tos(&self) -> u8 {
+        u8::from(self.dscp) << 2 | u8::from(self.ecn)
+    }
+
+    pub fn set_tos(&mut self, tos: u8) {
+        self.dscp = ux::u6::new(tos >> 2);
+        self.ecn = ux::u2::new(tos & 0b11);
+    }
+
+    pub fn dont_fragment(&self) -> bool {
+        u8::from(self.flags) & DONT_FRAGMENT != 0
+    }
+
+    pub fn set_dont_fragment(&mut self, dont_fragment: bool) {
+        let flags = u8::from(self.flags) & !DONT_FRAGMENT;
+        self.flags = ux::u3::new(if dont_fragment { flags | DONT_FRAGMENT } else { flags });
+    }
+
     pub fn parse(i: parse::Input) -> parse::Result<Self> {
         use ux::{u2, u3, u4, u6, u13};
         use nom::Offset;
@@ -213,26 +281,24 @@ impl Packet {
             bits(move |bo| {
                 let version = u4::new(4);
                 let ihl = u4::new(5);
-                let dscp = u6::new(0);
-                let ecn = u2::new(0);
 
                 version.write(bo);
                 ihl.write(bo);
-                dscp.write(bo);
-                ecn.write(bo);
+                self.dscp.write(bo);
+                self.ecn.write(bo);
             }),
             be_u16(0), // length, to fill later
             be_u16(self.identification),
             bits(move |bo| {
-                let flags = u3::new(0);
-                let fragment_offset = u13::new(0);
-
-                flags.write(bo);
-                fragment_offset.write(bo);
+                self.flags.write(bo);
+                self.fragment_offset.write(bo);
             }),
             be_u8(self.ttl),
             // we need to do this to avoid capturing a temporary
-            move |out| self.payload.protocol().serialize()(out),
+            move |out| match self.payload.protocol() {
+                Some(protocol) => protocol.serialize()(out),
+                None => Err(cf::GenError::NotYetImplemented),
+            },
             be_u16(0), // checksum, to fill later
             self.src.serialize(),
             self.dst.serialize(),
@@ -271,6 +337,22 @@ impl fmt::Debug for Addr {
     }
 }
 
+/// As a dotted quad string.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Addr {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(&format_args!("{:?}", self))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Addr {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ParseAddrError {
     #[error("too many octets")]
@@ -292,11 +374,11 @@ impl std::str::FromStr for Addr {
             let oct = tokens.next()
                             .ok_or(ParseAddrError::InsufficientOctets)?;
 
-            *part = u8::from_str_radix(oct, 10)
-                    .map_err(|e| ParseAddrError::InvalidOctet(e))?
+            *part = oct.parse::<u8>()
+                    .map_err(ParseAddrError::InvalidOctet)?
         }
 
-        if let Some(_) = tokens.next() {
+        if tokens.next().is_some() {
             return Err(ParseAddrError::TooManyOctets);
         }
 
@@ -304,12 +386,135 @@ impl std::str::FromStr for Addr {
     }
 }
 
-pub fn checksum(slice: &[u8]) -> u16 {
-    let (head, slice, tail) = unsafe { slice.align_to::<u16>() };
-    if head.is_empty() == false {
-        panic!("checksum() input should be 16-bit aligned");
+impl From<u32> for Addr {
+    fn from(x: u32) -> Self {
+        Self(x.to_be_bytes())
+    }
+}
+
+impl From<Addr> for u32 {
+    fn from(addr: Addr) -> Self {
+        u32::from_be_bytes(addr.0)
+    }
+}
+
+/// A block of addresses, like 10.0.0.0/24.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Cidr {
+    network: Addr,
+    prefix_len: u8,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseCidrError {
+    #[error("missing /prefix or /netmask")]
+    MissingPrefix,
+    #[error("invalid address: {0}")]
+    InvalidAddr(#[from] ParseAddrError),
+    #[error("invalid prefix length {0:?}")]
+    InvalidPrefix(String),
+    #[error("invalid netmask {0:?}")]
+    InvalidNetmask(Addr),
+}
+
+impl Cidr {
+    /// The block of `prefix_len` bits containing `addr`. Host bits are dropped.
+    pub fn new(addr: Addr, prefix_len: u8) -> Option<Self> {
+        if prefix_len > 32 {
+            return None;
+        }
+        let network = Addr::from(u32::from(addr) & mask(prefix_len));
+        Some(Self { network, prefix_len })
     }
 
+    /// Like `new`, with the prefix given as a netmask like 255.255.255.0.
+    pub fn with_netmask(addr: Addr, netmask: Addr) -> Option<Self> {
+        let bits = u32::from(netmask);
+        // netmasks are ones followed by zeroes
+        if bits.leading_ones() + bits.trailing_zeros() != 32 {
+            return None;
+        }
+        Self::new(addr, bits.leading_ones() as u8)
+    }
+
+    pub fn network(&self) -> Addr {
+        self.network
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn netmask(&self) -> Addr {
+        Addr::from(mask(self.prefix_len))
+    }
+
+    pub fn broadcast(&self) -> Addr {
+        Addr::from(u32::from(self.network) | !mask(self.prefix_len))
+    }
+
+    pub fn contains(&self, addr: Addr) -> bool {
+        u32::from(addr) & mask(self.prefix_len) == u32::from(self.network)
+    }
+
+    /// The addresses hosts can have: all but the network and broadcast
+    /// ones, except in /31 and /32 blocks which don't have those.
+    pub fn hosts(&self) -> impl Iterator<Item = Addr> {
+        let (first, last) = (u32::from(self.network), u32::from(self.broadcast()));
+        let (first, last) = if self.prefix_len >= 31 {
+            (first, last)
+        } else {
+            (first + 1, last - 1)
+        };
+        (first..=last).map(Addr::from)
+    }
+}
+
+fn mask(prefix_len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0)
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}/{}", self.network, self.prefix_len)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Cidr {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+/// From either form `FromStr` takes.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Cidr {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Parses "10.0.0.0/24" as well as "10.0.0.0/255.255.255.0".
+impl std::str::FromStr for Cidr {
+    type Err = ParseCidrError;
+
+    fn from_str(s: &str) -> Result<Self, ParseCidrError> {
+        let (addr, prefix) = s.split_once('/').ok_or(ParseCidrError::MissingPrefix)?;
+        let addr: Addr = addr.parse()?;
+
+        if prefix.contains('.') {
+            let netmask: Addr = prefix.parse()?;
+            return Self::with_netmask(addr, netmask).ok_or(ParseCidrError::InvalidNetmask(netmask));
+        }
+
+        let prefix_len = prefix.parse().map_err(|_| ParseCidrError::InvalidPrefix(prefix.to_string()))?;
+        Self::new(addr, prefix_len).ok_or_else(|| ParseCidrError::InvalidPrefix(prefix.to_string()))
+    }
+}
+
+pub fn checksum(slice: &[u8]) -> u16 {
     fn add(a: u16, b: u16) -> u16 {
         let s: u32 = (a as u32) + (b as u32);
         if s & 0x1_00_00 > 0 {
@@ -320,8 +525,11 @@ pub fn checksum(slice: &[u8]) -> u16 {
         }
     }
 
-    let odd_byte = tail.iter().next().map(|&x| x as u16).unwrap_or(0);
-    let sum = slice.iter().fold(odd_byte, |x, y| add(x, *y));
+    // summing native-endian words gives the checksum in native
+    // byte order, which is what callers write out as little-endian.
+    let words = slice.chunks_exact(2);
+    let odd_byte = words.remainder().first().map(|&x| u16::from_ne_bytes([x, 0])).unwrap_or(0);
+    let sum = words.fold(odd_byte, |x, y| add(x, u16::from_ne_bytes([y[0], y[1]])));
     !sum
 }
 
@@ -350,8 +558,76 @@ mod test {
         assert!(matches!(Addr::from_str("8.256.8.8"), Err(ParseAddrError::InvalidOctet(_))));
     }
 
+    #[test]
+    fn checksum_of_unaligned_slice() {
+        // a valid header, checksum included, sums up to zero
+        let buf = [
+            0x00, 0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+            0xb8, 0x61, 0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(checksum(&buf[1..]), 0);
+        assert_eq!(checksum(&buf[1..4]), checksum(&[0x45, 0x00, 0x00, 0x00]));
+    }
+
+    #[test]
+    fn serializing_unknown_payload_fails() {
+        let packet = Packet::new(Addr::zero(), Addr::zero(), Payload::Unknown);
+        assert!(matches!(
+            cf::gen_simple(packet.serialize(), Vec::new()),
+            Err(cf::GenError::NotYetImplemented)
+        ));
+    }
+
+    #[test]
+    fn tos_and_dont_fragment_survive_serialization() {
+        let echo = icmp::Echo {
+            identifier: 1,
+            sequence_number: 1,
+        };
+        let mut packet = Packet::new(Addr::zero(), Addr::zero(), Payload::ICMP(icmp::Packet::echo_request(echo, "")));
+        packet.set_tos(0xb9);
+        packet.set_dont_fragment(true);
+
+        let buf = cf::gen_simple(packet.serialize(), Vec::new()).unwrap();
+        assert_eq!(buf[1], 0xb9);
+        assert_eq!(buf[6], 0x40);
+
+        let (_, parsed) = Packet::parse(&buf).unwrap();
+        assert_eq!(parsed.tos(), 0xb9);
+        assert!(parsed.dont_fragment());
+    }
+
     #[test]
     fn parse_addr_with_correct_addres_is_ok() {
-        assert!(matches!(Addr::from_str("8.8.8.8"), Ok(_)));
+        assert!(Addr::from_str("8.8.8.8").is_ok());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_cidr() {
+        let cidr: Cidr = "10.0.0.77/24".parse().unwrap();
+        assert_eq!(cidr.network(), Addr([10, 0, 0, 0]));
+        assert_eq!(cidr.broadcast(), Addr([10, 0, 0, 255]));
+        assert_eq!(cidr.netmask(), Addr([255, 255, 255, 0]));
+        assert_eq!(cidr.to_string(), "10.0.0.0/24");
+        assert_eq!("10.0.0.0/255.255.255.0".parse::<Cidr>().unwrap(), cidr);
+
+        assert!(matches!("10.0.0.0".parse::<Cidr>(), Err(ParseCidrError::MissingPrefix)));
+        assert!(matches!("10.0.0.0/33".parse::<Cidr>(), Err(ParseCidrError::InvalidPrefix(_))));
+        assert!(matches!("10.0.0.0/255.0.255.0".parse::<Cidr>(), Err(ParseCidrError::InvalidNetmask(_))));
+    }
+
+    #[test]
+    fn cidr_hosts() {
+        let hosts = |s: &str| s.parse::<Cidr>().unwrap().hosts().collect::<Vec<_>>();
+        assert_eq!(hosts("10.0.0.0/30"), vec![Addr([10, 0, 0, 1]), Addr([10, 0, 0, 2])]);
+        assert_eq!(hosts("10.0.0.0/31"), vec![Addr([10, 0, 0, 0]), Addr([10, 0, 0, 1])]);
+        assert_eq!(hosts("10.0.0.9/32"), vec![Addr([10, 0, 0, 9])]);
+        let mut all = "0.0.0.0/0".parse::<Cidr>().unwrap().hosts();
+        assert_eq!(all.size_hint().0, (1 << 32) - 2);
+        assert_eq!(all.next(), Some(Addr([0, 0, 0, 1])));
+
+        let cidr: Cidr = "192.168.4.0/22".parse().unwrap();
+        assert!(cidr.contains(Addr([192, 168, 7, 255])));
+        assert!(!cidr.contains(Addr([192, 168, 8, 0])));
+    }
+}
@@ -0,0 +1,17 @@
+//! Helpers for how results look in JSON, where durations are
+//! milliseconds and addresses are strings.
+
+use serde::Serializer;
+use std::time;
+
+pub(crate) fn millis<S: Serializer>(d: &time::Duration, s: S) -> Result<S::Ok, S::Error> {
+    // dividing keeps e.g. 1.5ms from printing as 1.5000000000000002
+    s.serialize_f64(d.as_nanos() as f64 / 1e6)
+}
+
+pub(crate) fn opt_millis<S: Serializer>(d: &Option<time::Duration>, s: S) -> Result<S::Ok, S::Error> {
+    match *d {
+        Some(ref d) => millis(d, s),
+        None => s.serialize_none(),
+    }
+}
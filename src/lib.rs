@@ -8,6 +8,24 @@ pub mod icmp;
 pub mod blob;
 pub mod arp;
 pub mod serialize;
+pub mod bpf;
+pub mod demux;
+pub mod expectation;
+pub mod pcap;
+pub mod pcap_file;
+pub mod link;
 pub mod interface;
+pub mod asynchronous;
+pub mod pinger;
+pub mod sweep;
+pub mod arping;
+pub mod arp_scan;
+pub mod acd;
+pub mod arpwatch;
+pub mod dissect;
+pub mod sim;
+#[cfg(feature = "serde")]
+mod json;
 
-pub use interface::Interface;
\ No newline at end of file
+pub use interface::Interface;
+pub use pinger::Pinger;
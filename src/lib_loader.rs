@@ -3,27 +3,52 @@ use std::{
         c_void,
         CString,
     },
-    os::raw::{
-        c_char,
-    },
     mem::transmute_copy,
-    ptr::self,
+    ptr,
 };
+#[cfg(windows)]
+use std::os::raw::c_char;
+
+type Proc = ptr::NonNull<c_void>;
+type Module = ptr::NonNull<c_void>;
+
+#[cfg(windows)]
+extern "system" {
+    fn LoadLibraryA(name: *const c_char) -> Option<Module>;
+    fn GetProcAddress(module: Module, name: *const c_char) -> Option<Proc>;
+}
 
-type FarProc = ptr::NonNull<c_void>;
-type HModule = ptr::NonNull<c_void>;
-extern "stdcall" {
-    fn LoadLibraryA(name: *const c_char) -> Option<HModule>;
-    fn GetProcAddress(module: HModule, name: *const c_char) -> Option<FarProc>;
+#[cfg(windows)]
+unsafe fn open(name: &CString) -> Option<Module> {
+    LoadLibraryA(name.as_ptr())
+}
+
+#[cfg(windows)]
+unsafe fn symbol(module: Module, name: &CString) -> Option<Proc> {
+    GetProcAddress(module, name.as_ptr())
+}
+
+#[cfg(unix)]
+unsafe fn open(name: &CString) -> Option<Module> {
+    Module::new(libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL))
+}
+
+#[cfg(unix)]
+unsafe fn symbol(module: Module, name: &CString) -> Option<Proc> {
+    Proc::new(libc::dlsym(module.as_ptr(), name.as_ptr()))
 }
 
 #[derive(Debug)]
 pub struct Library {
     name: String,
-    module: HModule,
+    module: Module,
 }
 
-#[derive(thiserror::Error, Debug, PartialEq)]
+// module handles are process-wide, and so is everything looked up from them
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+#[derive(thiserror::Error, Debug, PartialEq, Clone)]
 pub enum LoadLibError {
     #[error("could not open library {0:?}")]
     NotFound(String),
@@ -31,7 +56,7 @@ pub enum LoadLibError {
     InvalidName(#[from] std::ffi::NulError),
 }
 
-#[derive(thiserror::Error, Debug, PartialEq)]
+#[derive(thiserror::Error, Debug, PartialEq, Clone)]
 pub enum GetProcError {
     #[error("could not find proc {proc:?} in {lib:?}")]
     NotFound{ proc: String, lib: String },
@@ -39,18 +64,44 @@ pub enum GetProcError {
     InvalidName(#[from] std::ffi::NulError),
 }
 
+/// Why a function declared with `bind!` couldn't be called.
+#[derive(thiserror::Error, Debug, PartialEq, Clone)]
+pub enum BindError {
+    #[error(transparent)]
+    Load(#[from] LoadLibError),
+    #[error(transparent)]
+    GetProc(#[from] GetProcError),
+}
+
 impl Library {
     pub fn new(name: &str) -> Result<Self, LoadLibError> {
         let c_name = CString::new(name)?;
-        let l = unsafe { LoadLibraryA(c_name.as_ptr()) };
+        let l = unsafe { open(&c_name) };
 
         l.map(|module| Library { name: name.to_owned(), module })
          .ok_or(LoadLibError::NotFound(name.to_owned()))
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Opens the first library in `names` that can be found.
+    pub fn first_of(names: &[&str]) -> Result<Self, LoadLibError> {
+        let mut err = LoadLibError::NotFound(names.join(", "));
+        for name in names {
+            match Self::new(name) {
+                Ok(lib) => return Ok(lib),
+                Err(e @ LoadLibError::InvalidName(_)) => err = e,
+                Err(_) => {}
+            }
+        }
+        Err(err)
+    }
+
     pub fn get_proc<T>(&self, name: &str) -> Result<T, GetProcError> {
         let c_name = CString::new(name)?;
-        let proc = unsafe { GetProcAddress(self.module, c_name.as_ptr()) };
+        let proc = unsafe { symbol(self.module, &c_name) };
 
         proc.map(|p| unsafe { transmute_copy(&p) })
             .ok_or(GetProcError::NotFound {
@@ -60,35 +111,115 @@ impl Library {
     }
 }
 
+/// Declares functions that are looked up in a library the first time
+/// any of them is called. Each of them returns a `Result`, which is an
+/// error if the library or the function couldn't be found.
+///
+/// The library is only loaded if all functions are found, except for
+/// those marked `optional`. `abi` defaults to `"system"`, which is
+/// `stdcall` on 32-bit Windows and the C calling convention elsewhere.
+///
+/// `library` takes one or more names to try in order, while `libraries`
+/// takes a `&[&str]`, which is handy when the names depend on the platform.
+///
+/// ```ignore
+/// bind! {
+///     library "first.dll", "fallback.dll";
+///     abi "C";
+///
+///     fn required(x: u32) -> u32;
+///     optional fn only_in_newer_versions() -> ();
+/// }
+/// ```
 #[macro_export]
 macro_rules! bind {
-    (library $lib:expr; $(fn $name:ident($($arg:ident: $type:ty),*) -> $ret:ty;)*) => {
+    (library $($lib:expr),+; $($rest:tt)*) => {
+        $crate::bind! { @abi (&[$($lib),+]) $($rest)* }
+    };
+    (libraries $libs:expr; $($rest:tt)*) => {
+        $crate::bind! { @abi ($libs) $($rest)* }
+    };
+    (@abi $libs:tt abi $abi:literal; $($rest:tt)*) => {
+        $crate::bind! { @fns $libs $abi [] $($rest)* }
+    };
+    (@abi $libs:tt $($rest:tt)*) => {
+        $crate::bind! { @fns $libs "system" [] $($rest)* }
+    };
+    // sort the functions into required and optional ones
+    (@fns $libs:tt $abi:literal [$($done:tt)*]
+        fn $name:ident($($arg:ident: $type:ty),*) -> $ret:ty; $($rest:tt)*) => {
+        $crate::bind! { @fns $libs $abi [$($done)* (required $name ($($arg: $type),*) $ret)] $($rest)* }
+    };
+    (@fns $libs:tt $abi:literal [$($done:tt)*]
+        optional fn $name:ident($($arg:ident: $type:ty),*) -> $ret:ty; $($rest:tt)*) => {
+        $crate::bind! { @fns $libs $abi [$($done)* (optional $name ($($arg: $type),*) $ret)] $($rest)* }
+    };
+    (@fns $libs:tt $abi:literal [$(($kind:ident $name:ident ($($arg:ident: $type:ty),*) $ret:ty))*]) => {
         struct Functions {
-            $(pub $name: extern "stdcall" fn ($($arg: $type),*) -> $ret),*
+            lib: String,
+            $($name: Option<extern $abi fn ($($arg: $type),*) -> $ret>),*
         }
 
-        static FUNCTIONS: once_cell::sync::Lazy<Functions> =
+        static FUNCTIONS: once_cell::sync::Lazy<Result<Functions, $crate::lib_loader::BindError>> =
             once_cell::sync::Lazy::new(|| {
-                let lib = crate::lib_loader::Library::new($lib).unwrap();
-                Functions {
-                    $($name: lib.get_proc(stringify!($name)).unwrap()),*
-                }
+                let lib = $crate::lib_loader::Library::first_of($libs)?;
+                Ok(Functions {
+                    lib: lib.name().to_owned(),
+                    $($name: $crate::bind!(@get lib $kind $name)),*
+                })
             });
 
         $(
             #[inline(always)]
-            pub fn $name($($arg: $type),*) -> $ret {
-                (FUNCTIONS.$name)($($arg),*)
+            pub fn $name($($arg: $type),*) -> Result<$ret, $crate::lib_loader::BindError> {
+                let functions = FUNCTIONS.as_ref().map_err(Clone::clone)?;
+                match functions.$name {
+                    Some(f) => Ok(f($($arg),*)),
+                    None => Err($crate::lib_loader::GetProcError::NotFound {
+                        proc: stringify!($name).to_owned(),
+                        lib: functions.lib.clone(),
+                    }.into()),
+                }
             }
         )*
     };
+    (@get $lib:ident required $name:ident) => {
+        Some($lib.get_proc(stringify!($name))?)
+    };
+    (@get $lib:ident optional $name:ident) => {
+        $lib.get_proc(stringify!($name)).ok()
+    };
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
-    const TEST_DLL_NAME: &str = "IPHLPAPI.dll";
+    #[cfg(windows)]
+    const TEST_LIB_NAME: &str = "IPHLPAPI.dll";
+    #[cfg(windows)]
+    const TEST_PROC_NAME: &str = "IcmpCreateFile";
+
+    #[cfg(unix)]
+    const TEST_LIB_NAME: &str = "libc.so.6";
+    #[cfg(unix)]
+    const TEST_PROC_NAME: &str = "getpid";
+
+    mod missing_lib {
+        crate::bind! {
+            library "non_existing_lib_name";
+
+            fn some_proc() -> u32;
+        }
+    }
+
+    mod optional_proc {
+        crate::bind! {
+            library super::TEST_LIB_NAME;
+
+            optional fn no_such_proc_name() -> u32;
+        }
+    }
 
     #[test]
     fn load_lib_invalid_name() {
@@ -104,27 +235,45 @@ mod test {
 
     #[test]
     fn load_lib_ok() {
-        assert!(Library::new(TEST_DLL_NAME).is_ok());
+        assert!(Library::new(TEST_LIB_NAME).is_ok());
+    }
+
+    #[test]
+    fn load_first_of_libs() {
+        let l = Library::first_of(&["non_existing_lib_name", TEST_LIB_NAME]);
+        assert!(l.is_ok());
     }
 
     #[test]
     fn get_proc_non_existent() {
-        let l = Library::new(TEST_DLL_NAME).unwrap();
+        let l = Library::new(TEST_LIB_NAME).unwrap();
         let p = l.get_proc::<fn() -> ()>("NoSuchProcName");
         assert!(matches!(p, Err(GetProcError::NotFound{ proc: _, lib: _ })));
     }
-    
+
     #[test]
     fn get_proc_invalid_name() {
-        let l = Library::new(TEST_DLL_NAME).unwrap();
+        let l = Library::new(TEST_LIB_NAME).unwrap();
         let p = l.get_proc::<fn() -> ()>("proc\0name");
         assert!(matches!(p, Err(GetProcError::InvalidName(_))));
     }
 
     #[test]
     fn get_proc_ok() {
-        let l = Library::new("IPHLPAPI.dll").unwrap();
-        let p = l.get_proc::<extern "stdcall" fn() -> *const c_void>("IcmpCreateFile");
+        let l = Library::new(TEST_LIB_NAME).unwrap();
+        let p = l.get_proc::<extern "system" fn() -> *const c_void>(TEST_PROC_NAME);
         assert!(p.is_ok());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bound_proc_in_missing_lib_is_error() {
+        let r = missing_lib::some_proc();
+        assert!(matches!(r, Err(BindError::Load(LoadLibError::NotFound(_)))));
+    }
+
+    #[test]
+    fn missing_optional_proc_is_error() {
+        let r = optional_proc::no_such_proc_name();
+        assert!(matches!(r, Err(BindError::GetProc(GetProcError::NotFound{ proc: _, lib: _ }))));
+    }
+}
@@ -0,0 +1,35 @@
+#[cfg(target_os = "linux")]
+pub mod socket;
+#[cfg(target_os = "linux")]
+pub mod tun;
+
+use crate::{
+    bpf,
+    error,
+};
+
+/// The kind of packets a `Link` sends and receives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    /// Whole Ethernet frames, e.g. from a packet capture driver.
+    Ethernet,
+    /// Bare IPv4 packets; there is no ARP or gateway MAC to deal with.
+    IPv4,
+}
+
+/// Something `Interface` can send raw packets through and receive them from.
+pub trait Link: Send + Sync {
+    fn layer(&self) -> Layer;
+
+    fn send(&self, packet: &[u8]) -> Result<(), error::Error>;
+
+    /// Passes every received packet to `callback` until `break_loop` is called.
+    fn loop_infinite(&self, callback: &mut dyn FnMut(&[u8])) -> Result<(), error::Error>;
+
+    fn break_loop(&self);
+
+    /// Links that can't filter in the kernel simply deliver everything.
+    fn set_filter(&self, _program: &bpf::Program) -> Result<(), error::Error> {
+        Ok(())
+    }
+}
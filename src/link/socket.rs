@@ -0,0 +1,504 @@
+use crate::{
+    error,
+    ipv4,
+    link,
+};
+use std::{
+    ffi::c_void,
+    io,
+    mem,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Mutex,
+    },
+};
+
+/// How often a blocked receive wakes up to check for `break_loop`.
+const RECV_TIMEOUT_US: libc::suseconds_t = 100_000;
+const IPV4_HEADER_LEN: usize = 20;
+const ICMP_HEADER_LEN: usize = 8;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// `SOCK_RAW`: needs CAP_NET_RAW, and the kernel hands us whole IPv4 packets.
+    Raw,
+    /// `SOCK_DGRAM`: allowed by `net.ipv4.ping_group_range`. The kernel strips
+    /// the IPv4 header and uses the socket's port as the echo identifier.
+    /// ICMP errors come through the socket's error queue, and are turned
+    /// back into packets.
+    Datagram,
+}
+
+/// A layer-3 link that only carries ICMP, over a plain Linux socket.
+/// No packet capture driver, ARP or gateway MAC is involved.
+pub struct IcmpSocket {
+    fd: libc::c_int,
+    kind: Kind,
+    identifier: Mutex<Option<u16>>,
+    // what the socket options are set to; held while sending, so that
+    // concurrent senders don't change them under each other's packets
+    header: Mutex<Option<HeaderOptions>>,
+    stop: AtomicBool,
+}
+
+/// The parts of the IPv4 header we choose through socket options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HeaderOptions {
+    ttl: libc::c_int,
+    tos: libc::c_int,
+    pmtu_discovery: libc::c_int,
+}
+
+impl IcmpSocket {
+    /// Opens a raw socket if we're allowed to, and a datagram socket otherwise.
+    pub fn open() -> Result<Self, error::Error> {
+        match Self::open_kind(Kind::Raw) {
+            Err(error::Error::IO(e)) if e.kind() == io::ErrorKind::PermissionDenied => {
+                Self::open_kind(Kind::Datagram)
+            }
+            res => res,
+        }
+    }
+
+    pub fn open_kind(kind: Kind) -> Result<Self, error::Error> {
+        let typ = match kind {
+            Kind::Raw => libc::SOCK_RAW,
+            Kind::Datagram => libc::SOCK_DGRAM,
+        };
+        let fd = unsafe { libc::socket(libc::AF_INET, typ | libc::SOCK_CLOEXEC, libc::IPPROTO_ICMP) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let socket = Self {
+            fd,
+            kind,
+            identifier: Mutex::new(None),
+            header: Mutex::new(None),
+            stop: AtomicBool::new(false),
+        };
+
+        let timeout = libc::timeval { tv_sec: 0, tv_usec: RECV_TIMEOUT_US };
+        socket.set_option(libc::SOL_SOCKET, libc::SO_RCVTIMEO, &timeout)?;
+        if kind == Kind::Datagram {
+            // we have to rebuild the IPv4 header, so ask for the TTL
+            socket.set_option(libc::IPPROTO_IP, libc::IP_RECVTTL, &(1 as libc::c_int))?;
+            // without this, ICMP errors about our packets are dropped
+            socket.set_option(libc::IPPROTO_IP, libc::IP_RECVERR, &(1 as libc::c_int))?;
+        }
+
+        Ok(socket)
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    fn set_option<T>(&self, level: libc::c_int, name: libc::c_int, value: &T) -> Result<(), error::Error> {
+        let ret = unsafe {
+            libc::setsockopt(
+                self.fd,
+                level,
+                name,
+                value as *const T as *const c_void,
+                mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+
+        match ret {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error().into()),
+        }
+    }
+
+    /// Datagram sockets stamp their port number on every echo request as
+    /// the identifier, so the socket gets bound to the first one we send.
+    fn bind_identifier(&self, identifier: u16) -> Result<(), error::Error> {
+        let mut bound = self.identifier.lock().unwrap();
+        match *bound {
+            Some(x) if x == identifier => Ok(()),
+            Some(x) => {
+                let msg = format!("socket is bound to ICMP identifier {:04x}", x);
+                Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into())
+            }
+            None => {
+                let addr = sockaddr(ipv4::Addr::zero(), identifier);
+                let ret = unsafe {
+                    libc::bind(
+                        self.fd,
+                        &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                    )
+                };
+                if ret < 0 {
+                    return Err(io::Error::last_os_error().into());
+                }
+
+                bound.replace(identifier);
+                Ok(())
+            }
+        }
+    }
+
+    /// Receives a single packet into `buf`, returning it as a whole IPv4 packet.
+    fn receive<'a>(&self, buf: &'a mut [u8]) -> io::Result<&'a [u8]> {
+        if self.kind == Kind::Raw {
+            let n = unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len(), 0) };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            return Ok(&buf[..n as usize]);
+        }
+
+        // an error that's already queued doesn't wake up recvmsg
+        if let Some(length) = self.receive_error(buf)? {
+            return Ok(&buf[..length]);
+        }
+
+        let (header, data) = buf.split_at_mut(IPV4_HEADER_LEN);
+        let mut src: libc::sockaddr_in = unsafe { mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut c_void,
+            iov_len: data.len(),
+        };
+        // u64s keep the control buffer aligned for cmsghdr
+        let mut control = [0u64; 8];
+
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut src as *mut libc::sockaddr_in as *mut c_void;
+        msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+
+        let n = unsafe { libc::recvmsg(self.fd, &mut msg, 0) };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            // an ICMP error arriving while we wait is reported as
+            // the error it stands for, e.g. EHOSTUNREACH
+            return match e.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted => Err(e),
+                _ => match self.receive_error(buf)? {
+                    Some(length) => Ok(&buf[..length]),
+                    None => Err(e),
+                },
+            };
+        }
+
+        let mut ttl = 0;
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let c = unsafe { &*cmsg };
+            if c.cmsg_level == libc::IPPROTO_IP && c.cmsg_type == libc::IP_TTL {
+                ttl = unsafe { *(libc::CMSG_DATA(cmsg) as *const libc::c_int) } as u8;
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+
+        let length = IPV4_HEADER_LEN + n as usize;
+        let src = ipv4::Addr(src.sin_addr.s_addr.to_ne_bytes());
+        write_ipv4_header(header, length, ttl, src, ipv4::Addr::zero());
+
+        Ok(&buf[..length])
+    }
+
+    /// Takes an ICMP error off the error queue of a datagram socket, if
+    /// there is one, and rebuilds the packet it came in. Errors that
+    /// didn't come from ICMP, like EMSGSIZE, are dropped. Returns the
+    /// length of the packet.
+    fn receive_error(&self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        loop {
+            let mut dst: libc::sockaddr_in = unsafe { mem::zeroed() };
+            // what the error quotes of our packet, from its ICMP header on
+            let mut quoted = [0u8; 576];
+            let mut iov = libc::iovec {
+                iov_base: quoted.as_mut_ptr() as *mut c_void,
+                iov_len: quoted.len(),
+            };
+            let mut control = [0u64; 16];
+
+            let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+            msg.msg_name = &mut dst as *mut libc::sockaddr_in as *mut c_void;
+            msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut c_void;
+            msg.msg_controllen = mem::size_of_val(&control) as _;
+
+            let n = unsafe { libc::recvmsg(self.fd, &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                return match e.kind() {
+                    io::ErrorKind::WouldBlock => Ok(None),
+                    _ => Err(e),
+                };
+            }
+
+            let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+            while !cmsg.is_null() {
+                let c = unsafe { &*cmsg };
+                if c.cmsg_level == libc::IPPROTO_IP && c.cmsg_type == libc::IP_RECVERR {
+                    let ee = unsafe { libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err };
+                    let err = unsafe { ee.read_unaligned() };
+                    if err.ee_origin == libc::SO_EE_ORIGIN_ICMP {
+                        let offender = unsafe { (libc::SO_EE_OFFENDER(ee) as *const libc::sockaddr_in).read_unaligned() };
+                        let offender = ipv4::Addr(offender.sin_addr.s_addr.to_ne_bytes());
+                        let dst = ipv4::Addr(dst.sin_addr.s_addr.to_ne_bytes());
+                        let quoted = &quoted[..n as usize];
+                        return Ok(Some(icmp_error_packet(buf, err.ee_type, err.ee_code, offender, dst, quoted)));
+                    }
+                }
+                cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+            }
+        }
+    }
+}
+
+impl link::Link for IcmpSocket {
+    fn layer(&self) -> link::Layer {
+        link::Layer::IPv4
+    }
+
+    fn send(&self, packet: &[u8]) -> Result<(), error::Error> {
+        // the kernel builds the IPv4 header, we only
+        // keep the parts of it that it lets us choose.
+        let ihl = (packet.first().copied().unwrap_or(0) & 0x0F) as usize * 4;
+        if ihl < IPV4_HEADER_LEN || packet.len() < ihl + 8 {
+            let msg = "not an IPv4 packet carrying ICMP";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into());
+        }
+        let options = HeaderOptions {
+            ttl: packet[8] as libc::c_int,
+            tos: packet[1] as libc::c_int,
+            pmtu_discovery: if packet[6] & 0x40 != 0 {
+                libc::IP_PMTUDISC_DO
+            } else {
+                libc::IP_PMTUDISC_WANT
+            },
+        };
+        let dst = ipv4::Addr([packet[16], packet[17], packet[18], packet[19]]);
+        let icmp = &packet[ihl..];
+
+        if self.kind == Kind::Datagram && icmp[0] == ICMP_ECHO_REQUEST {
+            self.bind_identifier(u16::from_be_bytes([icmp[4], icmp[5]]))?;
+        }
+
+        let mut header = self.header.lock().unwrap();
+        if *header != Some(options) {
+            // forget them in case we fail halfway
+            *header = None;
+            self.set_option(libc::IPPROTO_IP, libc::IP_TTL, &options.ttl)?;
+            self.set_option(libc::IPPROTO_IP, libc::IP_TOS, &options.tos)?;
+            self.set_option(libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, &options.pmtu_discovery)?;
+            *header = Some(options);
+        }
+
+        let addr = sockaddr(dst, 0);
+        let ret = unsafe {
+            libc::sendto(
+                self.fd,
+                icmp.as_ptr() as *const c_void,
+                icmp.len(),
+                0,
+                &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            )
+        };
+
+        match ret {
+            n if n < 0 => Err(io::Error::last_os_error().into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn loop_infinite(&self, callback: &mut dyn FnMut(&[u8])) -> Result<(), error::Error> {
+        let mut buf = vec![0u8; 65536];
+
+        while !self.stop.swap(false, Ordering::SeqCst) {
+            match self.receive(&mut buf) {
+                Ok(packet) => callback(packet),
+                Err(e) => match e.kind() {
+                    io::ErrorKind::WouldBlock
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted => continue,
+                    _ => return Err(e.into()),
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    fn break_loop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for IcmpSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+fn sockaddr(addr: ipv4::Addr, port: u16) -> libc::sockaddr_in {
+    libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: port.to_be(),
+        sin_addr: libc::in_addr { s_addr: u32::from_ne_bytes(addr.0) },
+        sin_zero: [0; 8],
+    }
+}
+
+/// Fills in the header a datagram socket strips off. We don't know our own
+/// address here, so where it would go is left as 0.0.0.0.
+fn write_ipv4_header(buf: &mut [u8], length: usize, ttl: u8, src: ipv4::Addr, dst: ipv4::Addr) {
+    buf[..IPV4_HEADER_LEN].fill(0);
+    buf[0] = 0x45; // version 4, 5 words
+    buf[2..4].copy_from_slice(&(length as u16).to_be_bytes());
+    buf[8] = ttl;
+    buf[9] = ipv4::Protocol::ICMP as u8;
+    buf[12..16].copy_from_slice(&src.0);
+    buf[16..20].copy_from_slice(&dst.0);
+}
+
+/// Writes into `buf` the ICMP error `offender` sent about the packet we
+/// sent to `dst`, of which `quoted` is what it quotes after the IPv4
+/// header. Returns the length of the packet.
+fn icmp_error_packet(buf: &mut [u8], typ: u8, code: u8, offender: ipv4::Addr, dst: ipv4::Addr, quoted: &[u8]) -> usize {
+    let quoted_len = IPV4_HEADER_LEN + quoted.len();
+    let length = IPV4_HEADER_LEN + ICMP_HEADER_LEN + quoted_len;
+    write_ipv4_header(buf, length, 0, offender, ipv4::Addr::zero());
+
+    let icmp = &mut buf[IPV4_HEADER_LEN..length];
+    icmp[..ICMP_HEADER_LEN].fill(0);
+    icmp[0] = typ;
+    icmp[1] = code;
+    write_ipv4_header(&mut icmp[ICMP_HEADER_LEN..], quoted_len, 0, ipv4::Addr::zero(), dst);
+    icmp[ICMP_HEADER_LEN + IPV4_HEADER_LEN..].copy_from_slice(quoted);
+    let checksum = ipv4::checksum(icmp);
+    icmp[2..4].copy_from_slice(&checksum.to_le_bytes());
+
+    length
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        demux,
+        icmp,
+        link::Link,
+    };
+    use std::{
+        sync::{
+            mpsc,
+            Arc,
+        },
+        thread,
+        time,
+    };
+
+    fn serialize(packet: ipv4::Packet) -> Vec<u8> {
+        cookie_factory::gen_simple(packet.serialize(), Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn queued_errors_become_icmp_packets() {
+        let echo = icmp::Echo {
+            identifier: 0x1234,
+            sequence_number: 7,
+        };
+        let request = icmp::Packet::echo_request(echo, "oppa");
+        let quoted = cookie_factory::gen_simple(request.serialize(), Vec::new()).unwrap();
+
+        let (offender, dst) = (ipv4::Addr([10, 0, 0, 1]), ipv4::Addr([192, 0, 2, 1]));
+        let mut buf = vec![0; 1500];
+        let length = icmp_error_packet(&mut buf, 11, 0, offender, dst, &quoted);
+        assert_eq!(ipv4::checksum(&buf[IPV4_HEADER_LEN..length]), 0);
+
+        let (_, packet) = ipv4::Packet::parse(&buf[..length]).unwrap();
+        assert_eq!(packet.src, offender);
+        match packet.payload {
+            ipv4::Payload::ICMP(ref icmp) => {
+                assert!(matches!(icmp.typ, icmp::Type::TimeExceeded(icmp::TimeExceeded::TTLExpired)))
+            }
+            ref payload => panic!("expected ICMP, got {:?}", payload),
+        }
+        let key = demux::Key::Echo {
+            peer: dst,
+            identifier: 0x1234,
+            sequence_number: 7,
+        };
+        assert_eq!(demux::Key::echo(&packet), Some(key));
+    }
+
+    /// Pings 127.0.0.1 through a socket of `kind`, returning the reply,
+    /// or `None` if we aren't allowed to open one.
+    fn ping_loopback(kind: Kind) -> Option<ipv4::Packet> {
+        let socket = match IcmpSocket::open_kind(kind) {
+            Ok(socket) => Arc::new(socket),
+            Err(e) => {
+                eprintln!("can't open a {:?} ICMP socket, skipping: {}", kind, e);
+                return None;
+            }
+        };
+
+        let echo = icmp::Echo {
+            identifier: 0xBEEF,
+            sequence_number: 1,
+        };
+        let loopback = ipv4::Addr([127, 0, 0, 1]);
+        let request = icmp::Packet::echo_request(echo, "oppa");
+        socket
+            .send(&serialize(ipv4::Packet::new(ipv4::Addr::zero(), loopback, ipv4::Payload::ICMP(request))))
+            .unwrap();
+
+        let (done, wait) = mpsc::channel::<()>();
+        let watchdog = {
+            let socket = socket.clone();
+            thread::spawn(move || {
+                let _ = wait.recv_timeout(time::Duration::from_secs(2));
+                socket.break_loop();
+            })
+        };
+        let mut reply = None;
+        socket
+            .loop_infinite(&mut |raw| {
+                let packet = match ipv4::Packet::parse(raw) {
+                    Ok((_, packet)) => packet,
+                    _ => return,
+                };
+                // raw sockets also see the request going out
+                if let ipv4::Payload::ICMP(icmp::Packet { header: icmp::Header::EchoReply(ref echo), .. }) = packet.payload {
+                    if echo.identifier == 0xBEEF && reply.is_none() {
+                        reply = Some(packet);
+                        socket.break_loop();
+                    }
+                }
+            })
+            .unwrap();
+        drop(done);
+        watchdog.join().unwrap();
+        reply
+    }
+
+    #[test]
+    fn raw_sockets_ping_loopback() {
+        if let Some(reply) = ping_loopback(Kind::Raw) {
+            assert_eq!(reply.src, ipv4::Addr([127, 0, 0, 1]));
+        }
+    }
+
+    #[test]
+    fn datagram_sockets_ping_loopback() {
+        if let Some(reply) = ping_loopback(Kind::Datagram) {
+            assert_eq!(reply.src, ipv4::Addr([127, 0, 0, 1]));
+            assert!(reply.ttl > 0);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{
+    error,
+    link,
+};
+use std::{
+    ffi::{
+        c_void,
+        CStr,
+    },
+    io,
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+};
+
+// _IOW('T', 202, int), see <linux/if_tun.h>
+const TUNSETIFF: libc::c_ulong = 0x4004_54CA;
+/// How often a blocked read wakes up to check for `break_loop`.
+const POLL_TIMEOUT_MS: libc::c_int = 100;
+
+#[repr(C)]
+struct IfReq {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    flags: libc::c_short,
+    // the rest of the union in `struct ifreq`
+    _pad: [u8; 22],
+}
+
+/// A layer-3 link over a Linux TUN device: every read and
+/// write is a single bare IPv4 (or IPv6) packet.
+pub struct Tun {
+    fd: libc::c_int,
+    name: String,
+    stop: AtomicBool,
+}
+
+impl Tun {
+    /// Attaches to the TUN device called `name`, creating it if needed
+    /// (which requires CAP_NET_ADMIN). Bringing it up and routing to it
+    /// is left to the usual tools, e.g. `ip link`.
+    pub fn open(name: &str) -> Result<Self, error::Error> {
+        let mut req = IfReq {
+            name: [0; libc::IFNAMSIZ],
+            flags: (libc::IFF_TUN | libc::IFF_NO_PI) as libc::c_short,
+            _pad: [0; 22],
+        };
+        // leave room for the terminating NUL
+        if name.len() >= req.name.len() || name.contains('\0') {
+            let msg = format!("invalid TUN device name {:?}", name);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into());
+        }
+        for (dst, src) in req.name.iter_mut().zip(name.bytes()) {
+            *dst = src as libc::c_char;
+        }
+
+        // c"" literals would need Rust 1.77
+        #[allow(clippy::manual_c_str_literals)]
+        let path = CStr::from_bytes_with_nul(b"/dev/net/tun\0").unwrap();
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // from here on `Drop` closes `fd`
+        let tun = Self {
+            fd,
+            name: name.to_owned(),
+            stop: AtomicBool::new(false),
+        };
+
+        if unsafe { libc::ioctl(fd, TUNSETIFF as _, &mut req as *mut IfReq) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(tun)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Waits until a packet can be read, or the poll timeout elapses.
+    fn readable(&self) -> io::Result<bool> {
+        let mut fds = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        match unsafe { libc::poll(&mut fds, 1, POLL_TIMEOUT_MS) } {
+            n if n < 0 => Err(io::Error::last_os_error()),
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+}
+
+impl link::Link for Tun {
+    fn layer(&self) -> link::Layer {
+        link::Layer::IPv4
+    }
+
+    fn send(&self, packet: &[u8]) -> Result<(), error::Error> {
+        let ret = unsafe { libc::write(self.fd, packet.as_ptr() as *const c_void, packet.len()) };
+
+        match ret {
+            n if n < 0 => Err(io::Error::last_os_error().into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn loop_infinite(&self, callback: &mut dyn FnMut(&[u8])) -> Result<(), error::Error> {
+        let mut buf = vec![0u8; 65536];
+
+        while !self.stop.swap(false, Ordering::SeqCst) {
+            let res = self.readable().and_then(|readable| {
+                if !readable {
+                    return Ok(0);
+                }
+                match unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len()) } {
+                    n if n < 0 => Err(io::Error::last_os_error()),
+                    n => Ok(n as usize),
+                }
+            });
+
+            match res {
+                Ok(0) => continue,
+                Ok(n) => callback(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn break_loop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for Tun {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::link::Link;
+    use std::{
+        sync::Arc,
+        thread,
+        time,
+    };
+
+    /// Creates a TUN device, or `None` if we aren't allowed to.
+    fn open(name: &str) -> Option<Tun> {
+        match Tun::open(name) {
+            Ok(tun) => Some(tun),
+            Err(e) => {
+                eprintln!("can't open TUN device {}, skipping: {}", name, e);
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        for name in ["oppa-too-long-name", "oppa\0"] {
+            match Tun::open(name) {
+                Err(error::Error::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+                res => panic!("expected {:?} to be rejected, got {:?}", name, res.map(|tun| tun.fd)),
+            }
+        }
+    }
+
+    #[test]
+    fn loop_stops_on_break() {
+        let tun = match open("oppatest0") {
+            Some(tun) => Arc::new(tun),
+            None => return,
+        };
+        assert_eq!(tun.name(), "oppatest0");
+        assert_eq!(tun.layer(), link::Layer::IPv4);
+
+        let breaker = {
+            let tun = tun.clone();
+            thread::spawn(move || {
+                thread::sleep(time::Duration::from_millis(50));
+                tun.break_loop();
+            })
+        };
+        let start = time::Instant::now();
+        tun.loop_infinite(&mut |_| {}).unwrap();
+        assert!(start.elapsed() < time::Duration::from_secs(2));
+        breaker.join().unwrap();
+    }
+}
@@ -1,74 +1,52 @@
-use oppa::{
-    ipv4,
-    icmp,
-    Interface,
-};
-use std::{
-    env,
-    process,
-    time,
-};
+use clap::Parser;
+
+mod cli;
+
+/// Sends ICMP echo requests to one or more hosts.
+#[derive(Parser, Debug)]
+#[clap(name = "oppa", args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Options {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Print results as text, or as JSON objects one per line
+    #[clap(long, arg_enum, global = true, default_value = "text")]
+    format: cli::Format,
+
+    #[clap(flatten)]
+    ping: cli::ping::Options,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Pings every host of an address block and lists those that answer
+    Sweep(cli::sweep::Options),
+    /// Sends ARP requests to a host on the local network
+    Arping(cli::arping::Options),
+    /// Lists the stations of the local network, with ARP requests
+    ArpScan(cli::arp_scan::Options),
+    /// Checks that nobody uses an address before taking it (RFC 5227)
+    ArpProbe(cli::arp_probe::Options),
+    /// Watches ARP traffic for new stations and changed MAC addresses
+    Arpwatch(cli::arpwatch::Options),
+    /// Breaks down frames from a pcap file or in hex, field by field
+    Decode(cli::decode::Options),
+    /// Prints a line about every frame the interface sees
+    Sniff(cli::sniff::Options),
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let arg = env::args().nth(1).unwrap_or_else(|| {
-        println!("Usage: oppa DEST");
-        process::exit(1);
-    });
-
-    let dest = arg.parse()?;
-
-    let mut iface = Interface::open_default()?;
-
-    let identifier = 0xBEEF;
-    let data = "Lorem ipsum dolor sit amet";
-    println!("Pinging {:?} with {} bytes of data:", dest, data.len());
-
-    for sequence_number in 0..4 {
-        let echo_pd = ipv4::Payload::ICMP(icmp::Packet::echo_request(
-            icmp::Echo {
-                identifier,
-                sequence_number,
-            },
-            data.as_bytes(),
-        ));
-
-        let before = time::Instant::now();
-        let rx = iface.expect_ipv4(move |packet| {
-            if let ipv4::Payload::ICMP(ref icmp_packet) = packet.payload {
-                if let icmp::Header::EchoReply(ref reply) = icmp_packet.header {
-                    if reply.identifier == identifier && reply.sequence_number == sequence_number {
-                        return Some((before.elapsed(), packet.clone()));
-                    }
-                }
-            }
-
-            None
-        });
-
-        iface.send_ipv4(echo_pd, &dest)?;
-
-        match rx.recv_timeout(time::Duration::from_secs(3)) {
-            Ok((elapsed, packet)) => {
-                if let ipv4::Payload::ICMP(ref icmp_packet) = packet.payload {
-                    if let icmp::Header::EchoReply(_) = icmp_packet.header {
-                        println!(
-                            "Reply from {:?}: bytes={} time={:?} TTL={}",
-                            packet.src,
-                            icmp_packet.payload.0.len(),
-                            elapsed,
-                            packet.ttl,
-                        );
-                    }
-                }
-            }
-            Err(_) => {
-                println!("Timed out!");
-                process::exit(1);
-            }
-        }
-
-        std::thread::sleep(time::Duration::from_secs(1));
+    let opts = Options::parse();
+    let format = opts.format;
+
+    match opts.command {
+        Some(Command::Sweep(opts)) => cli::sweep::run(opts, format),
+        Some(Command::Arping(opts)) => cli::arping::run(opts, format),
+        Some(Command::ArpScan(opts)) => cli::arp_scan::run(opts, format),
+        Some(Command::ArpProbe(opts)) => cli::arp_probe::run(opts, format),
+        Some(Command::Arpwatch(opts)) => cli::arpwatch::run(opts, format),
+        Some(Command::Decode(opts)) => cli::decode::run(opts, format),
+        Some(Command::Sniff(opts)) => cli::sniff::run(opts, format),
+        None => cli::ping::run(opts.ping, format),
     }
-
-    Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,130 @@
+use super::{
+    Error,
+    NIC,
+};
+use crate::{
+    error,
+    ethernet,
+    ipv4,
+};
+use std::{
+    ffi::CStr,
+    fs,
+    ptr,
+};
+
+// see <linux/route.h>
+const RTF_UP: u32 = 0x1;
+const RTF_GATEWAY: u32 = 0x2;
+
+/// The interface and gateway of the default route, from `/proc/net/route`,
+/// the address and mask of that interface, from `getifaddrs`, and its MAC
+/// address, from `/sys/class/net`.
+pub fn default_nic() -> Result<NIC, error::Error> {
+    let routes = fs::read_to_string("/proc/net/route")?;
+    let (name, gateway) = default_route(&routes).ok_or(Error::DefaultRouteMissing)?;
+    let (address, mask) = ipv4_address(&name)?.ok_or(Error::DefaultInterfaceNoIPAddr)?;
+
+    let phy_address = fs::read_to_string(format!("/sys/class/net/{}/address", name))
+        .ok()
+        .and_then(|s| s.trim().parse::<ethernet::Addr>().ok())
+        .ok_or(Error::DefaultInterfaceNoMACAddr)?;
+
+    Ok(NIC {
+        guid: String::new(),
+        name,
+        gateway,
+        address,
+        mask,
+        phy_address,
+    })
+}
+
+/// The interface and gateway of the default route with the lowest metric.
+fn default_route(table: &str) -> Option<(String, ipv4::Addr)> {
+    // Iface Destination Gateway Flags RefCnt Use Metric Mask ..., where
+    // addresses and flags are hex, and addresses in host byte order
+    let hex = |s: &str| u32::from_str_radix(s, 16).ok();
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let (name, dest, gateway, flags, metric, mask) = match fields[..] {
+                [name, dest, gateway, flags, _, _, metric, mask, ..] => (name, dest, gateway, flags, metric, mask),
+                _ => return None,
+            };
+            let flags = hex(flags)?;
+            let wanted = RTF_UP | RTF_GATEWAY;
+            if hex(dest)? != 0 || hex(mask)? != 0 || flags & wanted != wanted {
+                return None;
+            }
+            let gateway = ipv4::Addr(hex(gateway)?.to_ne_bytes());
+            Some((metric.parse::<u32>().ok()?, name.to_owned(), gateway))
+        })
+        .min_by_key(|(metric, ..)| *metric)
+        .map(|(_, name, gateway)| (name, gateway))
+}
+
+/// The first IPv4 address of interface `name`, and its mask.
+fn ipv4_address(name: &str) -> Result<Option<(ipv4::Addr, ipv4::Addr)>, error::Error> {
+    let mut addrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    let to_addr = |sa: *const libc::sockaddr| {
+        let sa = unsafe { &*(sa as *const libc::sockaddr_in) };
+        ipv4::Addr(sa.sin_addr.s_addr.to_ne_bytes())
+    };
+
+    let mut found = None;
+    let mut current = addrs;
+    while let Some(ifa) = unsafe { current.as_ref() } {
+        current = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() || ifa.ifa_netmask.is_null() {
+            continue;
+        }
+        let family = unsafe { (*ifa.ifa_addr).sa_family };
+        let ifa_name = unsafe { CStr::from_ptr(ifa.ifa_name) };
+        if i32::from(family) == libc::AF_INET && ifa_name.to_bytes() == name.as_bytes() {
+            found = Some((to_addr(ifa.ifa_addr), to_addr(ifa.ifa_netmask)));
+            break;
+        }
+    }
+
+    unsafe { libc::freeifaddrs(addrs) };
+    Ok(found)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_route_with_lowest_metric() {
+        let table = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+eth0\t00000000\t010000C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t000000C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+";
+        assert_eq!(default_route(table), Some(("eth0".to_owned(), "192.0.0.1".parse().unwrap())));
+
+        // routes that are down, or without a gateway, don't count
+        let table = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t00000000\t010000C0\t0002\t0\t0\t100\t00000000\t0\t0\t0
+tun0\t00000000\t00000000\t0001\t0\t0\t0\t00000000\t0\t0\t0
+";
+        assert_eq!(default_route(table), None);
+    }
+
+    #[test]
+    fn loopback_has_an_address() {
+        let (address, mask) = ipv4_address("lo").unwrap().unwrap();
+        assert_eq!(address, "127.0.0.1".parse().unwrap());
+        assert_eq!(mask, "255.0.0.0".parse().unwrap());
+        assert_eq!(ipv4_address("no-such-interface").unwrap(), None);
+    }
+}
@@ -1,5 +1,4 @@
 use std::{
-    mem,
     ptr,
     marker::PhantomData,
     ops::Deref,
@@ -7,8 +6,10 @@ use std::{
 };
 use crate::{
     error::Error,
+    lib_loader::BindError,
 };
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct VLS<T> {
     data: Vec<u8>,
     _phantom: PhantomData<T>
@@ -16,27 +17,27 @@ pub struct VLS<T> {
 
 impl<T> VLS<T> {
     pub fn new<F>(f: F) -> Result<Self, Error>
-        where F: Fn(*mut T, *mut u32) -> u32,
+        where F: Fn(*mut T, *mut u32) -> Result<u32, BindError>,
     {
         const ERROR_INSUFFICIENT_BUFFER: u32 = 122;
         const ERROR_BUFFER_OVERFLOW: u32 = 111;
 
         let mut size = 0;
-        match f(ptr::null_mut(), &mut size) {
+        match f(ptr::null_mut(), &mut size)? {
             ERROR_INSUFFICIENT_BUFFER => {},
             ERROR_BUFFER_OVERFLOW  => {},
             ret => return Err(Error::Win32(ret)),
         };
 
         let mut v = vec![0u8; size as usize];
-        match f(unsafe { mem::transmute(v.as_mut_ptr()) }, &mut size) {
+        match f(v.as_mut_ptr() as *mut T, &mut size)? {
             0 => {}
             r => return Err(Error::Win32(r)),
         };
 
         Ok(Self {
             data: v,
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
         })
     }
 }
@@ -45,12 +46,12 @@ impl<T> Deref for VLS<T> {
     type Target = T;
 
     fn deref(&self) -> &T {
-        unsafe { mem::transmute(self.data.as_ptr()) }
+        unsafe { &*(self.data.as_ptr() as *const T) }
     }
 }
 
 impl<T> DerefMut for VLS<T> {
     fn deref_mut(&mut self) -> &mut T {
-        unsafe { mem::transmute(self.data.as_ptr()) }
+        unsafe { &mut *(self.data.as_mut_ptr() as *mut T) }
     }
 }
\ No newline at end of file
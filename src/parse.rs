@@ -92,9 +92,9 @@ where
     }
 }
 
-impl<'a> fmt::Debug for Error<&'a [u8]> {
+impl fmt::Debug for Error<&[u8]> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "/!\\ oppa parsing error\n")?;
+        writeln!(f, "/!\\ oppa parsing error")?;
 
         let mut shown_input = None;
         let margin_left = 4;
@@ -128,7 +128,7 @@ impl<'a> fmt::Debug for Error<&'a [u8]> {
                 for b in s {
                     write!(f, "{:02X} ", b)?;
                 }
-                write!(f, "\n")?;
+                writeln!(f)?;
 
                 write!(f, "{}", margin_str)?;
                 for i in 0..s.len() {
@@ -142,7 +142,7 @@ impl<'a> fmt::Debug for Error<&'a [u8]> {
                         write!(f, "   ")?;
                     };
                 }
-                write!(f, "\n")?;
+                writeln!(f)?;
 
                 Ok(())
             };
@@ -151,10 +151,10 @@ impl<'a> fmt::Debug for Error<&'a [u8]> {
             let prefix = match kind {
                 ErrorKind::Context(ctx) => format!("...in {}", ctx),
                 ErrorKind::Nom(err) => format!("nom error {:?}", err),
-                ErrorKind::Custom(msg) => format!("{}", msg),
+                ErrorKind::Custom(msg) => msg.to_string(),
             };
 
-            write!(f, "{}\n", prefix)?;
+            writeln!(f, "{}", prefix)?;
             match shown_input {
                 None => {
                     shown_input.replace(input);
@@ -0,0 +1,159 @@
+//! Live capture through libpcap (or Npcap on Windows), loaded at runtime.
+//!
+//! This used to go through the rawsock crate, but rawsock 0.3 has no way to
+//! install a BPF filter: its interface traits don't expose `pcap_setfilter`
+//! and keep the pcap handle private. It also opens devices with a 1 second
+//! read timeout, which holds back replies and skews RTTs. The handful of
+//! functions we need are bound here instead, see `bind!`.
+
+use crate::{
+    bpf,
+    error,
+    link,
+};
+use std::{
+    ffi::{
+        c_void,
+        CStr,
+        CString,
+    },
+    os::raw::{
+        c_char,
+        c_int,
+        c_long,
+        c_uchar,
+        c_uint,
+    },
+    ptr,
+    slice,
+};
+
+type PcapHandle = ptr::NonNull<c_void>;
+type Handler = extern "C" fn(user: *mut c_uchar, header: *const PacketHeader, bytes: *const c_uchar);
+
+#[cfg(windows)]
+const LIBRARIES: &[&str] = &["Npcap\\wpcap.dll", "wpcap.dll"];
+#[cfg(target_os = "macos")]
+const LIBRARIES: &[&str] = &["libpcap.A.dylib", "libpcap.dylib"];
+#[cfg(all(unix, not(target_os = "macos")))]
+const LIBRARIES: &[&str] = &["libpcap.so.1", "libpcap.so", "libpcap.so.0.8"];
+
+crate::bind! {
+    libraries LIBRARIES;
+    abi "C";
+
+    fn pcap_open_live(device: *const c_char, snaplen: c_int, promisc: c_int, to_ms: c_int, errbuf: *mut c_char) -> Option<PcapHandle>;
+    fn pcap_close(p: PcapHandle) -> ();
+    fn pcap_sendpacket(p: PcapHandle, buf: *const c_uchar, size: c_int) -> c_int;
+    fn pcap_loop(p: PcapHandle, count: c_int, callback: Handler, user: *mut c_uchar) -> c_int;
+    fn pcap_breakloop(p: PcapHandle) -> ();
+    fn pcap_setfilter(p: PcapHandle, program: *mut BpfProgram) -> c_int;
+    fn pcap_geterr(p: PcapHandle) -> *const c_char;
+}
+
+const ERRBUF_SIZE: usize = 256;
+const SNAPLEN: c_int = 65535;
+// keep this low: replies sitting in the driver's buffer skew RTTs
+const READ_TIMEOUT_MS: c_int = 1;
+const PCAP_ERROR_BREAK: c_int = -2;
+
+#[repr(C)]
+pub struct Timeval {
+    pub sec: c_long,
+    pub usec: c_long,
+}
+
+#[repr(C)]
+pub struct PacketHeader {
+    pub ts: Timeval,
+    pub caplen: c_uint,
+    pub len: c_uint,
+}
+
+#[repr(C)]
+pub struct BpfProgram {
+    pub len: c_uint,
+    pub instructions: *const bpf::Instruction,
+}
+
+/// An open live capture on a single network device.
+pub struct Capture {
+    handle: PcapHandle,
+}
+
+// pcap allows sending and setting filters while another thread
+// is in `pcap_loop`, and `pcap_breakloop` is meant to be called
+// from a different thread.
+unsafe impl Send for Capture {}
+unsafe impl Sync for Capture {}
+
+impl Capture {
+    pub fn open(name: &str) -> Result<Self, error::Error> {
+        let c_name = CString::new(name).map_err(|e| error::Error::Pcap(e.to_string()))?;
+        let mut errbuf = [0 as c_char; ERRBUF_SIZE];
+
+        pcap_open_live(c_name.as_ptr(), SNAPLEN, 1, READ_TIMEOUT_MS, errbuf.as_mut_ptr())?
+            .map(|handle| Self { handle })
+            .ok_or_else(|| error::Error::Pcap(to_string(errbuf.as_ptr())))
+    }
+
+    fn last_error(&self) -> error::Error {
+        // we have a handle, so the library is there
+        error::Error::Pcap(pcap_geterr(self.handle).map(to_string).unwrap_or_default())
+    }
+}
+
+impl link::Link for Capture {
+    fn layer(&self) -> link::Layer {
+        link::Layer::Ethernet
+    }
+
+    fn send(&self, packet: &[u8]) -> Result<(), error::Error> {
+        match pcap_sendpacket(self.handle, packet.as_ptr(), packet.len() as c_int)? {
+            0 => Ok(()),
+            _ => Err(self.last_error()),
+        }
+    }
+
+    fn set_filter(&self, program: &bpf::Program) -> Result<(), error::Error> {
+        // pcap copies the instructions, so they only need to live for the call
+        let mut fp = BpfProgram {
+            len: program.0.len() as c_uint,
+            instructions: program.0.as_ptr(),
+        };
+
+        match pcap_setfilter(self.handle, &mut fp)? {
+            0 => Ok(()),
+            _ => Err(self.last_error()),
+        }
+    }
+
+    fn loop_infinite(&self, mut callback: &mut dyn FnMut(&[u8])) -> Result<(), error::Error> {
+        let user = &mut callback as *mut &mut dyn FnMut(&[u8]);
+
+        match pcap_loop(self.handle, -1, on_packet, user as *mut c_uchar)? {
+            0 | PCAP_ERROR_BREAK => Ok(()),
+            _ => Err(self.last_error()),
+        }
+    }
+
+    fn break_loop(&self) {
+        pcap_breakloop(self.handle).unwrap_or(())
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        pcap_close(self.handle).unwrap_or(())
+    }
+}
+
+extern "C" fn on_packet(user: *mut c_uchar, header: *const PacketHeader, bytes: *const c_uchar) {
+    let callback = unsafe { &mut *(user as *mut &mut dyn FnMut(&[u8])) };
+    let packet = unsafe { slice::from_raw_parts(bytes, (*header).caplen as usize) };
+    callback(packet)
+}
+
+fn to_string(s: *const c_char) -> String {
+    unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned()
+}
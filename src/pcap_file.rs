@@ -0,0 +1,207 @@
+//! Reading capture files in the classic pcap format, as written by
+//! tcpdump and by Wireshark when saving as "pcap". pcapng isn't supported.
+
+use crate::{
+    error,
+    link,
+};
+use std::{
+    io,
+    time,
+};
+
+// the magic number, as read in the file's byte order, for
+// microsecond and nanosecond timestamps
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const MAGIC_PCAPNG: u32 = 0x0a0d_0d0a;
+
+// nothing sane captures more than that in one record
+const MAX_RECORD_LEN: u32 = 256 * 1024;
+
+// the link types we can make sense of
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_IPV4: u32 = 228;
+
+/// A captured frame.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// When it was captured, since the unix epoch.
+    pub timestamp: time::Duration,
+    /// What was captured of it, which may be less than `original_len`.
+    pub data: Vec<u8>,
+    pub original_len: u32,
+}
+
+pub struct Reader<R> {
+    inner: R,
+    big_endian: bool,
+    nanos: bool,
+    link_type: u32,
+}
+
+/// Whether `prefix` starts like a pcap file, in either byte order.
+pub fn is_pcap(prefix: &[u8]) -> bool {
+    match prefix.get(..4) {
+        Some(&[a, b, c, d]) => {
+            let le = u32::from_le_bytes([a, b, c, d]);
+            let be = u32::from_be_bytes([a, b, c, d]);
+            [le, be].iter().any(|&magic| magic == MAGIC_MICROS || magic == MAGIC_NANOS)
+        }
+        _ => false,
+    }
+}
+
+impl<R: io::Read> Reader<R> {
+    /// Reads the file header, failing if it isn't one of a pcap file.
+    pub fn new(mut inner: R) -> Result<Self, error::Error> {
+        let mut header = [0; 24];
+        inner.read_exact(&mut header)?;
+
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (big_endian, nanos) = match (magic, magic.swap_bytes()) {
+            (MAGIC_MICROS, _) => (false, false),
+            (MAGIC_NANOS, _) => (false, true),
+            (_, MAGIC_MICROS) => (true, false),
+            (_, MAGIC_NANOS) => (true, true),
+            (MAGIC_PCAPNG, _) => {
+                return Err(error::Error::Parse(
+                    "pcapng files aren't supported, save the capture as pcap".into(),
+                ))
+            }
+            _ => return Err(error::Error::Parse("not a pcap file".into())),
+        };
+
+        let mut reader = Self {
+            inner,
+            big_endian,
+            nanos,
+            link_type: 0,
+        };
+        reader.link_type = reader.u32_at(&header, 20);
+        Ok(reader)
+    }
+
+    /// The LINKTYPE_ value of the file's header.
+    pub fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    /// What the records start with, or `None` for link types we don't know.
+    pub fn layer(&self) -> Option<link::Layer> {
+        match self.link_type {
+            LINKTYPE_ETHERNET => Some(link::Layer::Ethernet),
+            LINKTYPE_RAW | LINKTYPE_IPV4 => Some(link::Layer::IPv4),
+            _ => None,
+        }
+    }
+
+    fn u32_at(&self, buf: &[u8], offset: usize) -> u32 {
+        let bytes = [buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]];
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn read_record(&mut self) -> Result<Option<Record>, error::Error> {
+        let mut header = [0; 16];
+        let mut read = 0;
+        while read < header.len() {
+            match self.inner.read(&mut header[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        // a file that ends between records is fine, not within one
+        match read {
+            0 => return Ok(None),
+            16 => {}
+            _ => return Err(error::Error::Parse("truncated pcap record header".to_owned())),
+        }
+
+        let secs = self.u32_at(&header, 0);
+        let fraction = self.u32_at(&header, 4);
+        let captured_len = self.u32_at(&header, 8);
+        let original_len = self.u32_at(&header, 12);
+        if captured_len > MAX_RECORD_LEN {
+            return Err(error::Error::Parse(format!("pcap record of {} bytes", captured_len)));
+        }
+
+        let mut data = vec![0; captured_len as usize];
+        self.inner.read_exact(&mut data)?;
+
+        let fraction = if self.nanos {
+            time::Duration::from_nanos(fraction.into())
+        } else {
+            time::Duration::from_micros(fraction.into())
+        };
+        Ok(Some(Record {
+            timestamp: time::Duration::from_secs(secs.into()) + fraction,
+            data,
+            original_len,
+        }))
+    }
+}
+
+impl<R: io::Read> Iterator for Reader<R> {
+    type Item = Result<Record, error::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn big_endian_file() -> Vec<u8> {
+        let mut file = Vec::new();
+        for x in [MAGIC_NANOS, 0x0002_0004, 0, 0, 65535, LINKTYPE_RAW] {
+            file.extend_from_slice(&x.to_be_bytes());
+        }
+        for (secs, nanos, data) in [(1, 500, &b"abc"[..]), (2, 0, &b""[..])] {
+            for x in [secs, nanos, data.len() as u32, 60] {
+                file.extend_from_slice(&x.to_be_bytes());
+            }
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    #[test]
+    fn reads_big_endian_records() {
+        let file = big_endian_file();
+        assert!(is_pcap(&file));
+
+        let reader = Reader::new(&file[..]).unwrap();
+        assert_eq!(reader.layer(), Some(link::Layer::IPv4));
+        let records: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp, time::Duration::new(1, 500));
+        assert_eq!(records[0].data, b"abc");
+        assert_eq!(records[0].original_len, 60);
+        assert!(records[1].data.is_empty());
+    }
+
+    #[test]
+    fn truncated_records_fail() {
+        let file = big_endian_file();
+        let mut reader = Reader::new(&file[..file.len() - 18]).unwrap();
+        assert!(matches!(reader.next(), Some(Err(error::Error::IO(_)))));
+
+        // the last record is all header
+        let mut reader = Reader::new(&file[..file.len() - 2]).unwrap();
+        assert!(matches!(reader.next(), Some(Ok(_))));
+        match reader.next() {
+            Some(Err(error::Error::Parse(message))) => assert_eq!(message, "truncated pcap record header"),
+            res => panic!("expected a parse error, got {:?}", res),
+        }
+        assert!(!is_pcap(b"4500"));
+    }
+}
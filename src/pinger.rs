@@ -0,0 +1,898 @@
+use crate::{
+    demux,
+    error,
+    expectation::{
+        self,
+        Expectation,
+    },
+    icmp,
+    ipv4,
+    Interface,
+};
+use std::{
+    collections::{
+        HashSet,
+        VecDeque,
+    },
+    fmt,
+    sync::mpsc,
+    time,
+};
+
+const DEFAULT_PATTERN: &[u8] = b"Lorem ipsum dolor sit amet";
+
+/// An echo reply to one of our probes.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Reply {
+    pub sequence_number: u16,
+    pub src: ipv4::Addr,
+    pub ttl: u8,
+    /// Size of the ICMP payload.
+    pub size: usize,
+    #[cfg_attr(feature = "serde", serde(rename = "rtt_ms", serialize_with = "crate::json::millis"))]
+    pub rtt: time::Duration,
+}
+
+/// Something that happened to a probe. In JSON, the kind of event
+/// is in a "type" field, next to those of the variant.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum PingEvent {
+    /// A probe went out, only with `PingerBuilder::sent_events`.
+    Sent { sequence_number: u16 },
+    Reply(Reply),
+    /// Another reply to a probe that was already answered.
+    Duplicate(Reply),
+    Timeout { sequence_number: u16 },
+    /// An ICMP error about the probe, e.g. from a router it didn't get past.
+    Error {
+        sequence_number: u16,
+        from: ipv4::Addr,
+        #[cfg_attr(feature = "serde", serde(rename = "icmp_type"))]
+        typ: icmp::Type,
+    },
+}
+
+impl PingEvent {
+    pub fn sequence_number(&self) -> u16 {
+        match *self {
+            Self::Reply(ref reply) | Self::Duplicate(ref reply) => reply.sequence_number,
+            Self::Sent { sequence_number }
+            | Self::Timeout { sequence_number }
+            | Self::Error { sequence_number, .. } => sequence_number,
+        }
+    }
+}
+
+/// A summary of ping events, fed to it one by one.
+/// Probes are counted once they've been answered or timed out.
+#[derive(Debug, Clone, Default)]
+pub struct Statistics {
+    pub transmitted: usize,
+    pub received: usize,
+    pub duplicates: usize,
+    pub errors: usize,
+    // the probes counted as transmitted, since one can get errors and then a
+    // reply. sequence numbers wrap around, but a probe half a cycle older
+    // than the newest is long over, so it's forgotten.
+    counted: HashSet<u16>,
+    rtt_min: Option<time::Duration>,
+    rtt_max: Option<time::Duration>,
+    // in seconds, to get the mean deviation without keeping every RTT
+    rtt_sum: f64,
+    rtt_sum_squares: f64,
+}
+
+impl Statistics {
+    pub fn record(&mut self, event: &PingEvent) {
+        match *event {
+            PingEvent::Sent { .. } => {}
+            PingEvent::Reply(ref reply) => {
+                self.count(reply.sequence_number);
+                self.received += 1;
+                self.rtt_min = Some(self.rtt_min.map_or(reply.rtt, |x| x.min(reply.rtt)));
+                self.rtt_max = Some(self.rtt_max.map_or(reply.rtt, |x| x.max(reply.rtt)));
+                let rtt = reply.rtt.as_secs_f64();
+                self.rtt_sum += rtt;
+                self.rtt_sum_squares += rtt * rtt;
+            }
+            PingEvent::Duplicate(_) => self.duplicates += 1,
+            PingEvent::Error { sequence_number, .. } => {
+                self.count(sequence_number);
+                self.errors += 1;
+            }
+            PingEvent::Timeout { sequence_number } => self.count(sequence_number),
+        }
+    }
+
+    fn count(&mut self, sequence_number: u16) {
+        self.counted.remove(&sequence_number.wrapping_add(1 << 15));
+        if self.counted.insert(sequence_number) {
+            self.transmitted += 1;
+        }
+    }
+
+    /// Percentage of probes that weren't answered with a reply.
+    pub fn loss(&self) -> f64 {
+        match self.transmitted {
+            0 => 0.0,
+            n => (n - self.received) as f64 * 100.0 / n as f64,
+        }
+    }
+
+    pub fn rtt_min(&self) -> Option<time::Duration> {
+        self.rtt_min
+    }
+
+    pub fn rtt_max(&self) -> Option<time::Duration> {
+        self.rtt_max
+    }
+
+    pub fn rtt_avg(&self) -> Option<time::Duration> {
+        match self.received {
+            0 => None,
+            n => Some(time::Duration::from_secs_f64(self.rtt_sum / n as f64)),
+        }
+    }
+
+    /// The standard deviation of RTTs, which ping calls the mean deviation.
+    pub fn rtt_mdev(&self) -> Option<time::Duration> {
+        match self.received {
+            0 => None,
+            n => {
+                let avg = self.rtt_sum / n as f64;
+                let variance = self.rtt_sum_squares / n as f64 - avg * avg;
+                Some(time::Duration::from_secs_f64(variance.max(0.0).sqrt()))
+            }
+        }
+    }
+}
+
+/// The counters, plus the loss and RTTs in milliseconds (null without replies).
+#[cfg(feature = "serde")]
+impl serde::Serialize for Statistics {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        struct Millis(Option<time::Duration>);
+        impl serde::Serialize for Millis {
+            fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                crate::json::opt_millis(&self.0, s)
+            }
+        }
+
+        let mut st = s.serialize_struct("Statistics", 9)?;
+        st.serialize_field("transmitted", &self.transmitted)?;
+        st.serialize_field("received", &self.received)?;
+        st.serialize_field("duplicates", &self.duplicates)?;
+        st.serialize_field("errors", &self.errors)?;
+        st.serialize_field("loss_percent", &self.loss())?;
+        st.serialize_field("rtt_min_ms", &Millis(self.rtt_min()))?;
+        st.serialize_field("rtt_avg_ms", &Millis(self.rtt_avg()))?;
+        st.serialize_field("rtt_max_ms", &Millis(self.rtt_max()))?;
+        st.serialize_field("rtt_mdev_ms", &Millis(self.rtt_mdev()))?;
+        st.end()
+    }
+}
+
+/// The summary lines ping prints, without the header naming the destination.
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} packets transmitted, {} received", self.transmitted, self.received)?;
+        if self.duplicates > 0 {
+            write!(f, ", +{} duplicates", self.duplicates)?;
+        }
+        if self.errors > 0 {
+            write!(f, ", +{} errors", self.errors)?;
+        }
+        write!(f, ", {}% packet loss", self.loss().round())?;
+
+        if let (Some(min), Some(avg), Some(max), Some(mdev)) =
+            (self.rtt_min(), self.rtt_avg(), self.rtt_max(), self.rtt_mdev())
+        {
+            let ms = |d: time::Duration| d.as_secs_f64() * 1000.0;
+            write!(
+                f,
+                "\nrtt min/avg/max/mdev = {:.3}/{:.3}/{:.3}/{:.3} ms",
+                ms(min),
+                ms(avg),
+                ms(max),
+                ms(mdev)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends echo requests to a single destination. Build one with `Pinger::builder`.
+pub struct Pinger {
+    iface: Interface,
+    dest: ipv4::Addr,
+    count: Option<usize>,
+    interval: time::Duration,
+    adaptive: bool,
+    timeout: time::Duration,
+    deadline: Option<time::Duration>,
+    payload: Vec<u8>,
+    ttl: u8,
+    tos: u8,
+    dont_fragment: bool,
+    identifier: u16,
+    sent_events: bool,
+}
+
+pub struct PingerBuilder {
+    iface: Option<Interface>,
+    dest: ipv4::Addr,
+    count: Option<usize>,
+    interval: time::Duration,
+    adaptive: bool,
+    timeout: time::Duration,
+    deadline: Option<time::Duration>,
+    payload_size: usize,
+    pattern: Vec<u8>,
+    ttl: u8,
+    tos: u8,
+    dont_fragment: bool,
+    identifier: u16,
+    sent_events: bool,
+}
+
+impl Pinger {
+    pub fn builder(dest: ipv4::Addr) -> PingerBuilder {
+        PingerBuilder {
+            iface: None,
+            dest,
+            count: Some(4),
+            interval: time::Duration::from_secs(1),
+            adaptive: false,
+            timeout: time::Duration::from_secs(3),
+            deadline: None,
+            payload_size: DEFAULT_PATTERN.len(),
+            pattern: DEFAULT_PATTERN.to_vec(),
+            ttl: 128,
+            tos: 0,
+            dont_fragment: false,
+            identifier: rand::random(),
+            sent_events: false,
+        }
+    }
+
+    pub fn dest(&self) -> ipv4::Addr {
+        self.dest
+    }
+
+    /// Size of the ICMP payload of each probe.
+    pub fn payload_size(&self) -> usize {
+        self.payload.len()
+    }
+
+    pub fn iter(&self) -> Pings<'_> {
+        let (tx, rx) = mpsc::channel();
+        Pings {
+            pinger: self,
+            deadline: self.deadline.map(|deadline| time::Instant::now() + deadline),
+            next_sequence_number: 0,
+            sent: 0,
+            next_send: time::Instant::now(),
+            in_flight: VecDeque::new(),
+            tx,
+            rx,
+        }
+    }
+
+    /// Sends a probe, whose replies and errors go to `events`.
+    fn send(&self, sequence_number: u16, events: &mpsc::Sender<PingEvent>) -> Result<Probe, error::Error> {
+        let key = demux::Key::Echo {
+            peer: self.dest,
+            identifier: self.identifier,
+            sequence_number,
+        };
+        let sent_at = time::Instant::now();
+        let options = expectation::Options::forever().timeout(self.timeout);
+        let events = events.clone();
+        let expectation = self.iface.expect_keyed(key, options, move |packet| {
+            let icmp_packet = match packet.payload {
+                ipv4::Payload::ICMP(ref x) => x,
+                _ => return None,
+            };
+            let event = match icmp_packet.header {
+                icmp::Header::EchoReply(_) => PingEvent::Reply(Reply {
+                    sequence_number,
+                    src: packet.src,
+                    ttl: packet.ttl,
+                    size: icmp_packet.payload.0.len(),
+                    rtt: sent_at.elapsed(),
+                }),
+                // the demux only hands us errors quoting our request
+                _ => PingEvent::Error {
+                    sequence_number,
+                    from: packet.src,
+                    typ: icmp_packet.typ.clone(),
+                },
+            };
+            // probes share a channel, so the expectation itself never gets anything
+            let _ = events.send(event);
+            None::<()>
+        })?;
+
+        let echo = icmp::Echo {
+            identifier: self.identifier,
+            sequence_number,
+        };
+        let payload = ipv4::Payload::ICMP(icmp::Packet::echo_request(echo, &self.payload));
+        let mut packet = ipv4::Packet::new(self.iface.address(), self.dest, payload);
+        packet.ttl = self.ttl;
+        packet.set_tos(self.tos);
+        packet.set_dont_fragment(self.dont_fragment);
+        self.iface.send_ipv4_packet(packet)?;
+
+        Ok(Probe {
+            sequence_number,
+            sent_at,
+            answered: false,
+            replied: false,
+            _expectation: expectation,
+        })
+    }
+}
+
+impl<'a> IntoIterator for &'a Pinger {
+    type Item = Result<PingEvent, error::Error>;
+    type IntoIter = Pings<'a>;
+
+    fn into_iter(self) -> Pings<'a> {
+        self.iter()
+    }
+}
+
+impl PingerBuilder {
+    /// Pings through `iface` rather than one opened by `build`.
+    pub fn interface(self, iface: Interface) -> Self {
+        Self {
+            iface: Some(iface),
+            ..self
+        }
+    }
+
+    pub fn count(self, count: usize) -> Self {
+        Self {
+            count: Some(count),
+            ..self
+        }
+    }
+
+    /// Keeps pinging until the iterator is dropped.
+    pub fn continuous(self) -> Self {
+        Self { count: None, ..self }
+    }
+
+    /// Time between two probes.
+    pub fn interval(self, interval: time::Duration) -> Self {
+        Self { interval, ..self }
+    }
+
+    /// Sends the next probe as soon as every probe is answered, without
+    /// waiting for the interval, which becomes the longest time between two.
+    pub fn adaptive(self) -> Self {
+        Self { adaptive: true, ..self }
+    }
+
+    /// How long to wait for each reply.
+    pub fn timeout(self, timeout: time::Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    pub fn payload_size(self, payload_size: usize) -> Self {
+        Self { payload_size, ..self }
+    }
+
+    /// Bytes repeated to fill the payload.
+    pub fn pattern<P: AsRef<[u8]>>(self, pattern: P) -> Self {
+        Self {
+            pattern: pattern.as_ref().to_vec(),
+            ..self
+        }
+    }
+
+    /// Stops the whole run after this long, however many probes were sent.
+    pub fn deadline(self, deadline: time::Duration) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    pub fn ttl(self, ttl: u8) -> Self {
+        Self { ttl, ..self }
+    }
+
+    /// The type of service byte of the probes.
+    pub fn tos(self, tos: u8) -> Self {
+        Self { tos, ..self }
+    }
+
+    pub fn dont_fragment(self, dont_fragment: bool) -> Self {
+        Self { dont_fragment, ..self }
+    }
+
+    /// Pingers sharing an interface may use the same identifier, since
+    /// replies are told apart by who they come from. Datagram ICMP sockets
+    /// even require it.
+    pub fn identifier(self, identifier: u16) -> Self {
+        Self { identifier, ..self }
+    }
+
+    /// Also yields `PingEvent::Sent` as each probe goes out.
+    pub fn sent_events(self) -> Self {
+        Self {
+            sent_events: true,
+            ..self
+        }
+    }
+
+    /// Opens the default interface, unless one was given.
+    pub fn build(self) -> Result<Pinger, error::Error> {
+        let iface = match self.iface {
+            Some(iface) => iface,
+            None => default_interface()?,
+        };
+        let payload = self
+            .pattern
+            .iter()
+            .copied()
+            .cycle()
+            .take(if self.pattern.is_empty() { 0 } else { self.payload_size })
+            .chain(std::iter::repeat(0))
+            .take(self.payload_size)
+            .collect();
+
+        Ok(Pinger {
+            iface,
+            dest: self.dest,
+            count: self.count,
+            interval: self.interval,
+            adaptive: self.adaptive,
+            timeout: self.timeout,
+            deadline: self.deadline,
+            payload,
+            ttl: self.ttl,
+            tos: self.tos,
+            dont_fragment: self.dont_fragment,
+            identifier: self.identifier,
+            sent_events: self.sent_events,
+        })
+    }
+}
+
+/// The interface `build` opens when none was given, for pingers to share.
+/// On Linux a plain ICMP socket is enough, no capture driver needed.
+#[cfg(target_os = "linux")]
+pub fn default_interface() -> Result<Interface, error::Error> {
+    Interface::open_icmp_socket()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn default_interface() -> Result<Interface, error::Error> {
+    Interface::open_default()
+}
+
+struct Probe {
+    sequence_number: u16,
+    sent_at: time::Instant,
+    answered: bool,
+    // an ICMP error answers a probe too, but doesn't make later replies duplicates
+    replied: bool,
+    // unregisters the probe once dropped
+    _expectation: Expectation<()>,
+}
+
+/// Sends probes as it's iterated, yielding what happens to them.
+/// Probes go out on schedule whether or not earlier ones were answered.
+/// Sending errors are yielded too, and don't stop the iteration.
+pub struct Pings<'a> {
+    pinger: &'a Pinger,
+    deadline: Option<time::Instant>,
+    next_sequence_number: u16,
+    sent: usize,
+    next_send: time::Instant,
+    // oldest first, so the first one is the next to time out
+    in_flight: VecDeque<Probe>,
+    tx: mpsc::Sender<PingEvent>,
+    rx: mpsc::Receiver<PingEvent>,
+}
+
+impl Pings<'_> {
+    fn done_sending(&self) -> bool {
+        matches!(self.pinger.count, Some(count) if self.sent >= count)
+    }
+
+    fn past_deadline(&self) -> bool {
+        matches!(self.deadline, Some(deadline) if time::Instant::now() >= deadline)
+    }
+
+    /// Sends the next probe, returning its sequence number.
+    fn send(&mut self) -> Result<u16, error::Error> {
+        let sequence_number = self.next_sequence_number;
+        self.next_sequence_number = sequence_number.wrapping_add(1);
+        self.sent += 1;
+
+        // keep to the schedule, unless we've fallen behind it
+        let now = time::Instant::now();
+        self.next_send = (self.next_send + self.pinger.interval).max(now);
+
+        let probe = self.pinger.send(sequence_number, &self.tx)?;
+        self.in_flight.push_back(probe);
+        Ok(sequence_number)
+    }
+
+    /// Turns what a probe's expectation matched into the event to yield,
+    /// or `None` if the probe is already gone.
+    fn answer(&mut self, event: PingEvent) -> Option<PingEvent> {
+        let sequence_number = event.sequence_number();
+        let probe = self
+            .in_flight
+            .iter_mut()
+            .find(|probe| probe.sequence_number == sequence_number)?;
+
+        let event = match event {
+            PingEvent::Reply(reply) if probe.replied => PingEvent::Duplicate(reply),
+            event => event,
+        };
+        probe.answered = true;
+        probe.replied |= matches!(event, PingEvent::Reply(_));
+
+        if self.pinger.adaptive && self.in_flight.iter().all(|probe| probe.answered) {
+            self.next_send = self.next_send.min(time::Instant::now());
+        }
+        Some(event)
+    }
+}
+
+impl Iterator for Pings<'_> {
+    type Item = Result<PingEvent, error::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.past_deadline() {
+                return None;
+            }
+
+            // answered probes are kept around to catch duplicates
+            let now = time::Instant::now();
+            while let Some(probe) = self.in_flight.front() {
+                if probe.sent_at + self.pinger.timeout > now {
+                    break;
+                }
+                let probe = self.in_flight.pop_front().unwrap();
+                if !probe.answered {
+                    return Some(Ok(PingEvent::Timeout {
+                        sequence_number: probe.sequence_number,
+                    }));
+                }
+            }
+
+            // no waiting for duplicates once everything was answered
+            let done_sending = self.done_sending();
+            if done_sending && self.in_flight.iter().all(|probe| probe.answered) {
+                return None;
+            }
+
+            if !done_sending && now >= self.next_send {
+                match self.send() {
+                    Ok(sequence_number) if self.pinger.sent_events => {
+                        return Some(Ok(PingEvent::Sent { sequence_number }));
+                    }
+                    Ok(_) => continue,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+
+            let mut until = self.in_flight.front().map(|probe| probe.sent_at + self.pinger.timeout);
+            if !done_sending {
+                until = Some(until.map_or(self.next_send, |until| until.min(self.next_send)));
+            }
+            if let Some(deadline) = self.deadline {
+                until = Some(until.map_or(deadline, |until| until.min(deadline)));
+            }
+            // there's always a probe in flight or one to send
+            let until = until.unwrap();
+
+            if let Ok(event) = self.rx.recv_timeout(until.saturating_duration_since(now)) {
+                if let Some(event) = self.answer(event) {
+                    return Some(Ok(event));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sim::{
+        fixture::*,
+        Conditions,
+        Host,
+    };
+    use std::{
+        thread,
+        time::{
+            Duration,
+            Instant,
+        },
+    };
+
+    fn reply(sequence_number: u16, rtt_ms: u64) -> Reply {
+        Reply {
+            sequence_number,
+            src: ipv4::Addr::zero(),
+            ttl: 64,
+            size: 0,
+            rtt: time::Duration::from_millis(rtt_ms),
+        }
+    }
+
+    #[test]
+    fn statistics_summarize_events() {
+        let mut stats = Statistics::default();
+        for event in [
+            PingEvent::Reply(reply(0, 10)),
+            PingEvent::Duplicate(reply(0, 12)),
+            PingEvent::Timeout { sequence_number: 1 },
+            PingEvent::Reply(reply(2, 30)),
+            PingEvent::Timeout { sequence_number: 3 },
+        ] {
+            stats.record(&event);
+        }
+
+        assert_eq!((stats.transmitted, stats.received, stats.duplicates), (4, 2, 1));
+        assert_eq!(stats.loss(), 50.0);
+        assert_eq!(stats.rtt_min(), Some(time::Duration::from_millis(10)));
+        assert_eq!(stats.rtt_max(), Some(time::Duration::from_millis(30)));
+        assert_eq!(
+            stats.to_string(),
+            "4 packets transmitted, 2 received, +1 duplicates, 50% packet loss\n\
+             rtt min/avg/max/mdev = 10.000/20.000/30.000/10.000 ms"
+        );
+    }
+
+    #[test]
+    fn statistics_count_each_probe_once() {
+        let mut stats = Statistics::default();
+        let error = |sequence_number| PingEvent::Error {
+            sequence_number,
+            from: ipv4::Addr::zero(),
+            typ: icmp::Type::from((3, 1)),
+        };
+        for event in [error(0), PingEvent::Reply(reply(0, 10)), error(1), error(1)] {
+            stats.record(&event);
+        }
+        assert_eq!((stats.transmitted, stats.received, stats.errors), (2, 1, 3));
+        assert_eq!(stats.loss(), 50.0);
+
+        // once sequence numbers wrap around, they count again
+        for sequence_number in (2..=u16::MAX).chain(0..2) {
+            stats.record(&PingEvent::Timeout { sequence_number });
+        }
+        assert_eq!(stats.transmitted, 65536 + 2);
+    }
+
+    #[test]
+    fn statistics_without_replies_have_no_rtt() {
+        let mut stats = Statistics::default();
+        stats.record(&PingEvent::Timeout { sequence_number: 0 });
+
+        assert_eq!(stats.rtt_avg(), None);
+        assert_eq!(stats.to_string(), "1 packets transmitted, 0 received, 100% packet loss");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn events_serialize_to_documented_json() {
+        fn json<T: serde::Serialize>(x: &T) -> String {
+            serde_json::to_string(x).unwrap()
+        }
+
+        assert_eq!(
+            json(&PingEvent::Reply(reply(3, 12))),
+            r#"{"type":"reply","sequence_number":3,"src":"0.0.0.0","ttl":64,"size":0,"rtt_ms":12.0}"#
+        );
+        assert_eq!(json(&PingEvent::Sent { sequence_number: 2 }), r#"{"type":"sent","sequence_number":2}"#);
+        assert_eq!(json(&PingEvent::Timeout { sequence_number: 4 }), r#"{"type":"timeout","sequence_number":4}"#);
+        let error = PingEvent::Error {
+            sequence_number: 5,
+            from: ipv4::Addr([10, 0, 0, 1]),
+            typ: icmp::Type::from((11, 0)),
+        };
+        assert_eq!(
+            json(&error),
+            r#"{"type":"error","sequence_number":5,"from":"10.0.0.1","icmp_type":{"type":11,"code":0,"name":"time_exceeded"}}"#
+        );
+
+        let mut stats = Statistics::default();
+        stats.record(&PingEvent::Timeout { sequence_number: 0 });
+        assert_eq!(
+            json(&stats),
+            r#"{"transmitted":1,"received":0,"duplicates":0,"errors":0,"loss_percent":100.0,"#.to_owned()
+                + r#""rtt_min_ms":null,"rtt_avg_ms":null,"rtt_max_ms":null,"rtt_mdev_ms":null}"#
+        );
+    }
+
+    #[test]
+    fn pinger_reports_replies_and_timeouts() {
+        let (net, wan, iface) = routed();
+        net.host(wan, Host { echo: false, ..Host::new(mac(106), ip("10.1.0.6")) });
+
+        let pinger = Pinger::builder(ip("10.1.0.5"))
+            .interface(iface.clone())
+            .count(2)
+            .interval(Duration::from_millis(10))
+            .payload_size(40)
+            .build()
+            .unwrap();
+        let events: Vec<_> = pinger.iter().map(Result::unwrap).collect();
+        assert_eq!(events.len(), 2);
+        for (i, event) in events.iter().enumerate() {
+            match *event {
+                PingEvent::Reply(ref reply) => {
+                    assert_eq!(reply.sequence_number, i as u16);
+                    assert_eq!(reply.src, ip("10.1.0.5"));
+                    assert_eq!(reply.size, 40);
+                }
+                ref event => panic!("expected a reply, got {:?}", event),
+            }
+        }
+
+        let pinger = Pinger::builder(ip("10.1.0.6"))
+            .interface(iface)
+            .count(1)
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let events: Vec<_> = pinger.iter().map(Result::unwrap).collect();
+        assert!(matches!(events[..], [PingEvent::Timeout { sequence_number: 0 }]));
+    }
+
+    #[test]
+    fn pinger_reports_sends_when_asked() {
+        let (_net, _wan, iface) = routed();
+        let pinger = Pinger::builder(ip("10.1.0.5"))
+            .interface(iface)
+            .count(2)
+            .interval(Duration::from_millis(10))
+            .sent_events()
+            .build()
+            .unwrap();
+        let events: Vec<_> = pinger.iter().map(Result::unwrap).collect();
+        let at = |wanted: fn(&PingEvent) -> bool, sequence_number| {
+            events
+                .iter()
+                .position(|event| wanted(event) && event.sequence_number() == sequence_number)
+                .unwrap()
+        };
+        assert_eq!(events.len(), 4);
+        // a reply may come in before or after the next request goes out
+        for sequence_number in 0..2 {
+            let sent = at(|event| matches!(event, PingEvent::Sent { .. }), sequence_number);
+            let reply = at(|event| matches!(event, PingEvent::Reply(_)), sequence_number);
+            assert!(sent < reply);
+        }
+    }
+
+    #[test]
+    fn continuous_pinger_stops_at_deadline() {
+        let (_net, _wan, iface) = routed();
+
+        let pinger = Pinger::builder(ip("10.1.0.5"))
+            .interface(iface)
+            .continuous()
+            .interval(Duration::from_millis(100))
+            .deadline(Duration::from_millis(250))
+            .build()
+            .unwrap();
+        let replies = pinger.iter().map(Result::unwrap).filter(|event| matches!(event, PingEvent::Reply(_)));
+        assert_eq!(replies.count(), 3);
+    }
+
+    #[test]
+    fn pinger_sends_on_schedule_while_waiting() {
+        let (net, wan, iface) = routed();
+        net.host(wan, Host { echo: false, ..Host::new(mac(106), ip("10.1.0.6")) });
+
+        let pinger = Pinger::builder(ip("10.1.0.6"))
+            .interface(iface)
+            .count(4)
+            .interval(Duration::from_millis(50))
+            .timeout(Duration::from_millis(300))
+            .build()
+            .unwrap();
+        let before = Instant::now();
+        let timeouts: Vec<_> = pinger.iter().map(|event| event.unwrap().sequence_number()).collect();
+        assert_eq!(timeouts, vec![0, 1, 2, 3]);
+        // one timeout after the last probe, rather than one per probe
+        assert!(before.elapsed() < Duration::from_millis(900));
+    }
+
+    #[test]
+    fn adaptive_pinger_doesnt_wait_for_interval() {
+        let (_net, _wan, iface) = routed();
+
+        let pinger = Pinger::builder(ip("10.1.0.5"))
+            .interface(iface)
+            .count(5)
+            .interval(Duration::from_secs(1))
+            .adaptive()
+            .build()
+            .unwrap();
+        let before = Instant::now();
+        let replies = pinger.iter().map(Result::unwrap).filter(|event| matches!(event, PingEvent::Reply(_)));
+        assert_eq!(replies.count(), 5);
+        assert!(before.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn pingers_share_an_interface_and_identifier() {
+        let (net, wan, iface) = routed();
+        net.host(wan, Host::new(mac(106), ip("10.1.0.6")));
+
+        let threads: Vec<_> = ["10.1.0.5", "10.1.0.6"]
+            .iter()
+            .map(|dest| {
+                let pinger = Pinger::builder(ip(dest))
+                    .interface(iface.clone())
+                    .identifier(0xBEEF)
+                    .count(3)
+                    .interval(Duration::from_millis(10))
+                    .build()
+                    .unwrap();
+                thread::spawn(move || pinger.iter().map(Result::unwrap).collect::<Vec<_>>())
+            })
+            .collect();
+
+        for (dest, t) in ["10.1.0.5", "10.1.0.6"].iter().zip(threads) {
+            let events = t.join().unwrap();
+            assert_eq!(events.len(), 3);
+            for (i, event) in events.iter().enumerate() {
+                assert!(matches!(event, PingEvent::Reply(reply) if reply.src == ip(dest) && reply.sequence_number == i as u16));
+            }
+        }
+    }
+
+    #[test]
+    fn pinger_reports_ttl_errors() {
+        let (_net, _wan, iface) = routed();
+
+        let pinger = Pinger::builder(ip("10.1.0.5")).interface(iface).count(1).ttl(1).build().unwrap();
+        let events: Vec<_> = pinger.iter().map(Result::unwrap).collect();
+        match events[..] {
+            [PingEvent::Error { from, ref typ, .. }] => {
+                assert_eq!(from, ip("10.0.0.1"));
+                assert!(matches!(typ, icmp::Type::TimeExceeded(icmp::TimeExceeded::TTLExpired)));
+            }
+            ref events => panic!("expected a TTL error, got {:?}", events),
+        }
+    }
+
+    #[test]
+    fn pinger_reports_duplicates() {
+        let (_net, _lan, iface) = lan_with(Conditions { duplication: 1.0, ..Default::default() });
+
+        // duplicates of the last probe are only waited for until the first reply
+        let pinger = Pinger::builder(ip("10.0.0.1"))
+            .interface(iface)
+            .count(2)
+            .interval(Duration::from_millis(300))
+            .build()
+            .unwrap();
+        let events: Vec<_> = pinger.iter().map(Result::unwrap).collect();
+        assert!(matches!(events[0], PingEvent::Reply(_)));
+        assert!(events[1..events.len() - 1]
+            .iter()
+            .all(|event| matches!(event, PingEvent::Duplicate(ref reply) if reply.sequence_number == 0)));
+        assert!(events.len() > 2);
+        assert!(matches!(events.last(), Some(PingEvent::Reply(ref reply)) if reply.sequence_number == 1));
+    }
+}
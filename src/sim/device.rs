@@ -0,0 +1,343 @@
+use super::{
+    SegmentId,
+    Station,
+};
+use crate::{
+    arp,
+    blob::Blob,
+    ethernet,
+    icmp,
+    ipv4,
+};
+
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const ETHERNET_HEADER_LEN: usize = 14;
+
+/// Something living inside the simulation, attached to one or more segments.
+pub(super) trait Device: Send {
+    /// Handles a frame that arrived on the device's `port`-th
+    /// attachment, returning frames to send as `(port, frame)`.
+    fn receive(&mut self, port: usize, frame: &[u8], stations: &[Station]) -> Vec<(usize, Vec<u8>)>;
+}
+
+/// A machine answering ARP requests for its address and, if
+/// `echo` is set, ICMP echo requests sent to it.
+#[derive(Debug, Clone)]
+pub struct Host {
+    pub mac: ethernet::Addr,
+    pub ip: ipv4::Addr,
+    pub echo: bool,
+    pub ttl: u8,
+}
+
+impl Host {
+    pub fn new(mac: ethernet::Addr, ip: ipv4::Addr) -> Self {
+        Self {
+            mac,
+            ip,
+            echo: true,
+            ttl: 64,
+        }
+    }
+}
+
+impl Device for Host {
+    fn receive(&mut self, _port: usize, frame: &[u8], _stations: &[Station]) -> Vec<(usize, Vec<u8>)> {
+        let frame = match ethernet::Frame::parse(frame) {
+            Ok((_, frame)) => frame,
+            _ => return Vec::new(),
+        };
+
+        let reply = match frame.payload {
+            ethernet::Payload::ARP(ref request) => arp_reply(request, self.mac, self.ip),
+            ethernet::Payload::IPv4(ref packet) if self.echo && packet.dst == self.ip => {
+                echo_reply(packet, self.ttl)
+                    .map(|packet| ipv4_frame(self.mac, frame.src, packet))
+            }
+            _ => None,
+        };
+
+        reply.into_iter().map(|reply| (0, reply)).collect()
+    }
+}
+
+/// One leg of a `Router`, in the `ip/prefix_len` subnet of `segment`.
+#[derive(Debug, Clone)]
+pub struct RouterPort {
+    pub segment: SegmentId,
+    pub mac: ethernet::Addr,
+    pub ip: ipv4::Addr,
+    pub prefix_len: u8,
+}
+
+/// Forwards IPv4 packets between its ports, decrementing their TTL and
+/// answering with ICMP Time Exceeded once it runs out. Destinations outside
+/// all of its subnets go to `gateway`, if any.
+#[derive(Debug, Clone)]
+pub struct Router {
+    pub ports: Vec<RouterPort>,
+    pub gateway: Option<ipv4::Addr>,
+}
+
+impl Router {
+    /// The port whose subnet contains `addr`.
+    fn port_for(&self, addr: ipv4::Addr) -> Option<usize> {
+        self.ports
+            .iter()
+            .position(|p| same_subnet(p.ip, addr, p.prefix_len))
+    }
+
+    /// Where to send a packet bound for `dst`: the outgoing port and the next hop.
+    fn route(&self, dst: ipv4::Addr) -> Option<(usize, ipv4::Addr)> {
+        match self.port_for(dst) {
+            Some(port) => Some((port, dst)),
+            None => {
+                let gateway = self.gateway?;
+                self.port_for(gateway).map(|port| (port, gateway))
+            }
+        }
+    }
+
+    /// Sends an ICMP error about `original`, whose header is
+    /// `header_len` bytes long, back where it came from.
+    fn icmp_error(
+        &self,
+        port: usize,
+        to: ethernet::Addr,
+        original: &[u8],
+        header_len: usize,
+        typ: icmp::Type,
+    ) -> (usize, Vec<u8>) {
+        // the original IPv4 header plus the first 8 bytes of its payload
+        let quoted = &original[..original.len().min(header_len + 8)];
+        let src = ipv4::Addr([original[12], original[13], original[14], original[15]]);
+
+        let error = icmp::Packet {
+            typ,
+            checksum: 0,
+            header: icmp::Header::Other(0),
+            payload: Blob::new(quoted),
+        };
+        let mut packet = ipv4::Packet::new(self.ports[port].ip, src, ipv4::Payload::ICMP(error));
+        packet.ttl = 64;
+
+        (port, ipv4_frame(self.ports[port].mac, to, packet))
+    }
+}
+
+impl Device for Router {
+    fn receive(&mut self, port: usize, raw: &[u8], stations: &[Station]) -> Vec<(usize, Vec<u8>)> {
+        let frame = match ethernet::Frame::parse(raw) {
+            Ok((_, frame)) => frame,
+            _ => return Vec::new(),
+        };
+        let me = &self.ports[port];
+
+        let packet = match frame.payload {
+            ethernet::Payload::ARP(ref request) => {
+                return arp_reply(request, me.mac, me.ip)
+                    .map(|reply| vec![(port, reply)])
+                    .unwrap_or_default();
+            }
+            ethernet::Payload::IPv4(ref packet) => packet,
+            _ => return Vec::new(),
+        };
+
+        if let Some(local) = self.ports.iter().find(|p| p.ip == packet.dst) {
+            return echo_reply(packet, 64)
+                .map(|reply| vec![(port, ipv4_frame(local.mac, frame.src, reply))])
+                .unwrap_or_default();
+        }
+
+        // options make the header longer than the 20 bytes we parsed
+        let original = &raw[ETHERNET_HEADER_LEN..];
+        let header_len = usize::from(u8::from(packet.ihl)) * 4;
+        if header_len < IPV4_MIN_HEADER_LEN || original.len() < header_len {
+            return Vec::new();
+        }
+
+        if packet.ttl <= 1 {
+            let typ = icmp::Type::TimeExceeded(icmp::TimeExceeded::TTLExpired);
+            return vec![self.icmp_error(port, frame.src, original, header_len, typ)];
+        }
+
+        let next = self.route(packet.dst).and_then(|(out, next_hop)| {
+            let segment = self.ports[out].segment;
+            stations
+                .iter()
+                .find(|s| s.segment == segment && s.ip == next_hop)
+                .map(|s| (out, s.mac))
+        });
+        let (out, next_mac) = match next {
+            Some(x) => x,
+            None => {
+                let typ = icmp::Type::DestinationUnreachable(icmp::DestinationUnreachable::HostUnreachable);
+                return vec![self.icmp_error(port, frame.src, original, header_len, typ)];
+            }
+        };
+
+        // forward the bytes as they are, so we don't
+        // need to understand everything we route.
+        let mut forwarded = raw.to_vec();
+        forwarded[..6].copy_from_slice(&next_mac.0);
+        forwarded[6..12].copy_from_slice(&self.ports[out].mac.0);
+
+        let header = &mut forwarded[ETHERNET_HEADER_LEN..ETHERNET_HEADER_LEN + header_len];
+        header[8] -= 1;
+        header[10..12].copy_from_slice(&[0, 0]);
+        let checksum = ipv4::checksum(header);
+        header[10..12].copy_from_slice(&checksum.to_le_bytes());
+
+        vec![(out, forwarded)]
+    }
+}
+
+fn same_subnet(a: ipv4::Addr, b: ipv4::Addr, prefix_len: u8) -> bool {
+    // `Network::router` makes sure prefix_len is at most 32
+    let mask = u32::MAX.checked_shl(32 - u32::from(prefix_len)).unwrap_or(0);
+    u32::from_be_bytes(a.0) & mask == u32::from_be_bytes(b.0) & mask
+}
+
+fn arp_reply(request: &arp::Packet, mac: ethernet::Addr, ip: ipv4::Addr) -> Option<Vec<u8>> {
+    match request.operation {
+        arp::Operation::Request if request.target_ip_addr == ip => {}
+        _ => return None,
+    }
+
+    let frame = ethernet::Frame {
+        src: mac,
+        dst: request.sender_hw_addr,
+        ether_type: Some(ethernet::EtherType::ARP),
+        payload: ethernet::Payload::ARP(arp::Packet {
+            operation: arp::Operation::Reply,
+            sender_hw_addr: mac,
+            sender_ip_addr: ip,
+            target_hw_addr: request.sender_hw_addr,
+            target_ip_addr: request.sender_ip_addr,
+        }),
+    };
+    Some(cookie_factory::gen_simple(frame.serialize(), Vec::new()).unwrap())
+}
+
+fn echo_reply(request: &ipv4::Packet, ttl: u8) -> Option<ipv4::Packet> {
+    let icmp_request = match request.payload {
+        ipv4::Payload::ICMP(ref x) => x,
+        _ => return None,
+    };
+    let echo = match icmp_request.header {
+        icmp::Header::EchoRequest(ref echo) => echo.clone(),
+        _ => return None,
+    };
+
+    let reply = icmp::Packet::echo_reply(echo, &icmp_request.payload.0);
+    let mut packet = ipv4::Packet::new(request.dst, request.src, ipv4::Payload::ICMP(reply));
+    packet.ttl = ttl;
+    Some(packet)
+}
+
+fn ipv4_frame(src: ethernet::Addr, dst: ethernet::Addr, packet: ipv4::Packet) -> Vec<u8> {
+    let frame = ethernet::Frame {
+        src,
+        dst,
+        ether_type: Some(ethernet::EtherType::IPv4),
+        payload: ethernet::Payload::IPv4(packet),
+    };
+    cookie_factory::gen_simple(frame.serialize(), Vec::new()).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sim::{
+        fixture::{
+            ip,
+            mac,
+        },
+        Network,
+    };
+
+    fn router() -> Router {
+        Router {
+            ports: vec![
+                RouterPort { segment: SegmentId(0), mac: mac(1), ip: ip("10.0.0.1"), prefix_len: 24 },
+                RouterPort { segment: SegmentId(1), mac: mac(101), ip: ip("10.1.0.1"), prefix_len: 24 },
+            ],
+            gateway: None,
+        }
+    }
+
+    /// An echo request from 10.0.0.2 to 10.1.0.5, with 4 bytes of IPv4 options.
+    fn frame_with_options(ttl: u8) -> Vec<u8> {
+        let echo = icmp::Echo {
+            identifier: 1,
+            sequence_number: 2,
+        };
+        let mut packet = ipv4::Packet::new(
+            ip("10.0.0.2"),
+            ip("10.1.0.5"),
+            ipv4::Payload::ICMP(icmp::Packet::echo_request(echo, "options")),
+        );
+        packet.ttl = ttl;
+        let mut frame = ipv4_frame(mac(2), mac(1), packet);
+
+        // four no-op options after the 20-byte header
+        let start = ETHERNET_HEADER_LEN;
+        let options = start + IPV4_MIN_HEADER_LEN;
+        frame.splice(options..options, [1, 1, 1, 1]);
+        frame[start] = 0x46;
+        let length = u16::from_be_bytes([frame[start + 2], frame[start + 3]]) + 4;
+        frame[start + 2..start + 4].copy_from_slice(&length.to_be_bytes());
+        frame[start + 10..start + 12].copy_from_slice(&[0, 0]);
+        let checksum = ipv4::checksum(&frame[start..options + 4]);
+        frame[start + 10..start + 12].copy_from_slice(&checksum.to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn subnets() {
+        assert!(same_subnet(ip("10.0.0.1"), ip("10.0.0.200"), 24));
+        assert!(!same_subnet(ip("10.0.0.1"), ip("10.0.1.1"), 24));
+        assert!(same_subnet(ip("10.0.0.1"), ip("192.168.0.1"), 0));
+        assert!(!same_subnet(ip("10.0.0.1"), ip("10.0.0.2"), 32));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid prefix length")]
+    fn prefixes_over_32_are_rejected() {
+        let mut router = router();
+        router.ports[0].prefix_len = 33;
+        Network::new(0).router(router);
+    }
+
+    #[test]
+    fn forwarding_keeps_options() {
+        let stations = [Station { segment: SegmentId(1), ip: ip("10.1.0.5"), mac: mac(105) }];
+        let frame = frame_with_options(10);
+        let out = router().receive(0, &frame, &stations);
+        assert_eq!(out.len(), 1);
+
+        let (port, forwarded) = &out[0];
+        assert_eq!(*port, 1);
+        let header = &forwarded[ETHERNET_HEADER_LEN..ETHERNET_HEADER_LEN + 24];
+        assert_eq!(header[8], 9);
+        assert_eq!(ipv4::checksum(header), 0);
+        assert_eq!(forwarded[ETHERNET_HEADER_LEN + 24..], frame[ETHERNET_HEADER_LEN + 24..]);
+    }
+
+    #[test]
+    fn time_exceeded_quotes_options() {
+        let frame = frame_with_options(1);
+        let out = router().receive(0, &frame, &[]);
+        let (_, reply) = &out[0];
+        let reply = match ethernet::Frame::parse(reply).unwrap().1.payload {
+            ethernet::Payload::IPv4(packet) => packet,
+            _ => panic!("expected IPv4"),
+        };
+        match reply.payload {
+            ipv4::Payload::ICMP(icmp) => {
+                assert_eq!(icmp.payload.0, frame[ETHERNET_HEADER_LEN..ETHERNET_HEADER_LEN + 24 + 8]);
+            }
+            _ => panic!("expected ICMP"),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Small networks and helpers shared by the tests that run on the simulator.
+
+use super::{
+    Conditions,
+    Host,
+    Network,
+    Port,
+    Router,
+    RouterPort,
+    SegmentId,
+};
+use crate::{
+    arp,
+    demux,
+    ethernet,
+    expectation::Options,
+    icmp,
+    ipv4,
+    netinfo,
+    Interface,
+};
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn mac(last: u8) -> ethernet::Addr {
+    ethernet::Addr([0x02, 0, 0, 0, 0, last])
+}
+
+pub fn ip(s: &str) -> ipv4::Addr {
+    s.parse().unwrap()
+}
+
+pub fn nic(address: &str, last: u8, gateway: &str) -> netinfo::NIC {
+    netinfo::NIC {
+        guid: String::new(),
+        name: String::new(),
+        gateway: ip(gateway),
+        address: ip(address),
+        mask: ip("255.255.255.0"),
+        phy_address: mac(last),
+    }
+}
+
+/// Opens `port` as 10.0.0.`last`, whose MAC ends in `last`, behind 10.0.0.1.
+pub fn open(port: Port, last: u8) -> Interface {
+    let address = format!("10.0.0.{}", last);
+    Interface::open_ethernet(Arc::new(port), nic(&address, last, "10.0.0.1")).unwrap()
+}
+
+/// Attaches a station to `segment` and opens it, see `open`.
+pub fn station(net: &Network, segment: SegmentId, last: u8) -> Interface {
+    let port = net.port(segment, mac(last), ip(&format!("10.0.0.{}", last)));
+    open(port, last)
+}
+
+/// A LAN with us on .2 and a host on .1.
+pub fn lan() -> (Network, SegmentId, Interface) {
+    lan_with(Conditions::default())
+}
+
+pub fn lan_with(conditions: Conditions) -> (Network, SegmentId, Interface) {
+    let net = Network::new(0);
+    let lan = net.segment(conditions);
+    net.host(lan, Host::new(mac(1), ip("10.0.0.1")));
+    let iface = station(&net, lan, 2);
+    (net, lan, iface)
+}
+
+/// A LAN with our port on .2 and a router on .1, leading to a WAN
+/// with a host on 10.1.0.5.
+pub fn routed_port() -> (Network, SegmentId, Port) {
+    let net = Network::new(0);
+    let lan = net.segment(Conditions::default());
+    let wan = net.segment(Conditions::default());
+    net.router(Router {
+        ports: vec![
+            RouterPort { segment: lan, mac: mac(1), ip: ip("10.0.0.1"), prefix_len: 24 },
+            RouterPort { segment: wan, mac: mac(101), ip: ip("10.1.0.1"), prefix_len: 24 },
+        ],
+        gateway: None,
+    });
+    net.host(wan, Host::new(mac(105), ip("10.1.0.5")));
+
+    let port = net.port(lan, mac(2), ip("10.0.0.2"));
+    (net, wan, port)
+}
+
+/// Like `routed_port`, with the port opened.
+pub fn routed() -> (Network, SegmentId, Interface) {
+    let (net, wan, port) = routed_port();
+    (net, wan, open(port, 2))
+}
+
+pub fn serialize(frame: ethernet::Frame) -> Vec<u8> {
+    cookie_factory::gen_simple(frame.serialize(), Vec::new()).unwrap()
+}
+
+pub fn arp_request(nic: &netinfo::NIC) -> Vec<u8> {
+    serialize(ethernet::Frame {
+        src: nic.phy_address,
+        dst: ethernet::Addr::broadcast(),
+        ether_type: Some(ethernet::EtherType::ARP),
+        payload: ethernet::Payload::ARP(arp::Packet::request(nic.phy_address, nic.address, nic.gateway)),
+    })
+}
+
+pub fn ping(iface: &Interface, dest: ipv4::Addr, sequence_number: u16) -> Option<ipv4::Packet> {
+    let key = demux::Key::Echo {
+        peer: dest,
+        identifier: 0xBEEF,
+        sequence_number,
+    };
+    let rx = iface
+        .expect_keyed(key, Options::once().timeout(TIMEOUT), is_echo_reply)
+        .unwrap();
+
+    let echo = icmp::Echo {
+        identifier: 0xBEEF,
+        sequence_number,
+    };
+    let payload = ipv4::Payload::ICMP(icmp::Packet::echo_request(echo, "sim"));
+    iface.send_ipv4(payload, &dest).unwrap();
+
+    rx.recv().ok()
+}
+
+pub fn is_echo_reply(packet: &ipv4::Packet) -> Option<ipv4::Packet> {
+    match packet.payload {
+        ipv4::Payload::ICMP(icmp::Packet { header: icmp::Header::EchoReply(_), .. }) => Some(packet.clone()),
+        _ => None,
+    }
+}
@@ -0,0 +1,585 @@
+//! An in-memory network for exercising `Interface` without a real NIC.
+//!
+//! Frames travel between the ports of a segment (a switched broadcast
+//! domain) after a configurable delay, and may be lost, duplicated,
+//! reordered or dropped for exceeding the MTU on the way. Hosts and
+//! routers live inside the simulation and answer ARP and ICMP.
+
+mod device;
+#[cfg(test)]
+pub(crate) mod fixture;
+
+pub use device::{
+    Host,
+    Router,
+    RouterPort,
+};
+
+use crate::{
+    bpf,
+    error,
+    ethernet,
+    ipv4,
+    link,
+};
+use device::Device;
+use rand::{
+    rngs::StdRng,
+    Rng,
+    SeedableRng,
+};
+use std::{
+    cmp::Ordering as CmpOrdering,
+    collections::BinaryHeap,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        mpsc,
+        Arc,
+        Condvar,
+        Mutex,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+const ETHERNET_HEADER_LEN: usize = 14;
+/// How often a `Port` waiting for frames checks for `break_loop`.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What happens to frames sent on a segment.
+#[derive(Debug, Clone)]
+pub struct Conditions {
+    /// Fixed delay before a frame reaches the other ports.
+    pub latency: Duration,
+    /// Random extra delay of up to this much; frames may get reordered.
+    pub jitter: Duration,
+    /// Probability of a frame being dropped, in `0.0..=1.0`.
+    pub loss: f64,
+    /// Probability of a frame being delivered twice, in `0.0..=1.0`.
+    pub duplication: f64,
+    /// Frames with a bigger payload are dropped.
+    pub mtu: usize,
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(1),
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            duplication: 0.0,
+            mtu: 1500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentId(usize);
+
+/// Someone attached to a segment who frames get delivered to.
+enum Endpoint {
+    /// A port, which gets every frame of the segment if it's a mirror.
+    Port { tx: mpsc::Sender<Vec<u8>>, mirror: bool },
+    Device { device: usize, port: usize },
+}
+
+struct Segment {
+    conditions: Conditions,
+    endpoints: Vec<(ethernet::Addr, Endpoint)>,
+}
+
+/// An address the simulation knows about, which stands in for
+/// the ARP tables of routers.
+#[derive(Debug, Clone, Copy)]
+pub struct Station {
+    pub segment: SegmentId,
+    pub ip: ipv4::Addr,
+    pub mac: ethernet::Addr,
+}
+
+struct Scheduled {
+    at: Instant,
+    // breaks ties between frames due at the same instant
+    seq: u64,
+    segment: SegmentId,
+    // the endpoint that sent the frame, which doesn't get it back
+    from: usize,
+    frame: Vec<u8>,
+}
+
+// `BinaryHeap` is a max-heap, so the earliest frame must compare greatest.
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+struct AttachedDevice {
+    device: Box<dyn Device>,
+    // where each port is attached, as (segment, index of the endpoint in it)
+    ports: Vec<(SegmentId, usize)>,
+}
+
+struct State {
+    segments: Vec<Segment>,
+    devices: Vec<AttachedDevice>,
+    stations: Vec<Station>,
+    queue: BinaryHeap<Scheduled>,
+    rng: StdRng,
+    seq: u64,
+    stopped: bool,
+}
+
+impl State {
+    fn attach(&mut self, segment: SegmentId, mac: ethernet::Addr, endpoint: Endpoint) -> usize {
+        let endpoints = &mut self.segments[segment.0].endpoints;
+        endpoints.push((mac, endpoint));
+        endpoints.len() - 1
+    }
+
+    fn transmit(&mut self, segment: SegmentId, from: usize, frame: Vec<u8>) {
+        let conditions = &self.segments[segment.0].conditions;
+        if frame.len() > ETHERNET_HEADER_LEN + conditions.mtu {
+            return;
+        }
+        if self.rng.gen_bool(conditions.loss) {
+            return;
+        }
+
+        let copies = if self.rng.gen_bool(conditions.duplication) { 2 } else { 1 };
+        for _ in 0..copies {
+            let jitter = conditions.jitter.mul_f64(self.rng.gen::<f64>());
+            self.seq += 1;
+            self.queue.push(Scheduled {
+                at: Instant::now() + conditions.latency + jitter,
+                seq: self.seq,
+                segment,
+                from,
+                frame: frame.clone(),
+            });
+        }
+    }
+
+    fn deliver(&mut self, s: Scheduled) {
+        let dst = match ethernet::Addr::parse(&s.frame) {
+            Ok((_, dst)) => dst,
+            _ => return,
+        };
+
+        let mut replies = Vec::new();
+        for (i, (mac, endpoint)) in self.segments[s.segment.0].endpoints.iter().enumerate() {
+            let mirror = matches!(endpoint, Endpoint::Port { mirror: true, .. });
+            if i == s.from || (*mac != dst && dst != ethernet::Addr::broadcast() && !mirror) {
+                continue;
+            }
+
+            match *endpoint {
+                Endpoint::Port { ref tx, .. } => {
+                    // nobody listening is the same as the frame getting lost
+                    tx.send(s.frame.clone()).unwrap_or(());
+                }
+                Endpoint::Device { device, port } => {
+                    let attached = &mut self.devices[device];
+                    for (out_port, frame) in attached.device.receive(port, &s.frame, &self.stations) {
+                        let (segment, from) = attached.ports[out_port];
+                        replies.push((segment, from, frame));
+                    }
+                }
+            }
+        }
+
+        for (segment, from, frame) in replies {
+            self.transmit(segment, from, frame);
+        }
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    wakeup: Condvar,
+}
+
+/// A simulated network, whose frames are moved around by a background
+/// thread. Dropping it stops the thread; its ports then go quiet.
+pub struct Network {
+    shared: Arc<Shared>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Network {
+    /// Creates an empty network. Losses, duplicates and jitter are
+    /// drawn from a generator seeded with `seed`.
+    pub fn new(seed: u64) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                segments: Vec::new(),
+                devices: Vec::new(),
+                stations: Vec::new(),
+                queue: BinaryHeap::new(),
+                rng: StdRng::seed_from_u64(seed),
+                seq: 0,
+                stopped: false,
+            }),
+            wakeup: Condvar::new(),
+        });
+
+        let thread_shared = shared.clone();
+        let thread = thread::spawn(move || run(&thread_shared));
+
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn segment(&self, conditions: Conditions) -> SegmentId {
+        let mut state = self.shared.state.lock().unwrap();
+        state.segments.push(Segment {
+            conditions,
+            endpoints: Vec::new(),
+        });
+        SegmentId(state.segments.len() - 1)
+    }
+
+    /// Changes the conditions of a segment, affecting frames sent from now on.
+    pub fn set_conditions(&self, segment: SegmentId, conditions: Conditions) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.segments[segment.0].conditions = conditions;
+    }
+
+    /// Attaches a port for an `Interface` to use, see `Interface::open_ethernet`.
+    pub fn port(&self, segment: SegmentId, mac: ethernet::Addr, ip: ipv4::Addr) -> Port {
+        self.attach_port(segment, mac, ip, false)
+    }
+
+    /// Like `port`, but it also gets the frames sent to others,
+    /// as the mirror port of a switch would.
+    pub fn mirror_port(&self, segment: SegmentId, mac: ethernet::Addr, ip: ipv4::Addr) -> Port {
+        self.attach_port(segment, mac, ip, true)
+    }
+
+    fn attach_port(&self, segment: SegmentId, mac: ethernet::Addr, ip: ipv4::Addr, mirror: bool) -> Port {
+        let (tx, rx) = mpsc::channel();
+
+        let mut state = self.shared.state.lock().unwrap();
+        let endpoint = state.attach(segment, mac, Endpoint::Port { tx, mirror });
+        state.stations.push(Station { segment, ip, mac });
+
+        Port {
+            shared: self.shared.clone(),
+            segment,
+            endpoint,
+            rx: Mutex::new(rx),
+            filter: Mutex::new(None),
+            stop: AtomicBool::new(false),
+        }
+    }
+
+    pub fn host(&self, segment: SegmentId, host: Host) {
+        let station = Station {
+            segment,
+            ip: host.ip,
+            mac: host.mac,
+        };
+        self.add_device(Box::new(host), vec![station]);
+    }
+
+    /// Panics if a port's `prefix_len` is over 32.
+    pub fn router(&self, router: Router) {
+        for p in &router.ports {
+            assert!(p.prefix_len <= 32, "invalid prefix length /{} for {:?}", p.prefix_len, p.ip);
+        }
+        let stations = router
+            .ports
+            .iter()
+            .map(|p| Station {
+                segment: p.segment,
+                ip: p.ip,
+                mac: p.mac,
+            })
+            .collect();
+        self.add_device(Box::new(router), stations);
+    }
+
+    fn add_device(&self, device: Box<dyn Device>, stations: Vec<Station>) {
+        let mut state = self.shared.state.lock().unwrap();
+        let index = state.devices.len();
+
+        let mut ports = Vec::new();
+        for (port, station) in stations.iter().enumerate() {
+            let endpoint = Endpoint::Device { device: index, port };
+            ports.push((station.segment, state.attach(station.segment, station.mac, endpoint)));
+        }
+
+        state.devices.push(AttachedDevice { device, ports });
+        state.stations.extend(stations);
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stopped = true;
+        self.shared.wakeup.notify_all();
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap_or(());
+        }
+    }
+}
+
+fn run(shared: &Shared) {
+    let mut state = shared.state.lock().unwrap();
+    while !state.stopped {
+        let now = Instant::now();
+        state = match state.queue.peek() {
+            Some(s) if s.at <= now => {
+                let s = state.queue.pop().unwrap();
+                state.deliver(s);
+                state
+            }
+            Some(s) => {
+                let timeout = s.at - now;
+                shared.wakeup.wait_timeout(state, timeout).unwrap().0
+            }
+            None => shared.wakeup.wait(state).unwrap(),
+        };
+    }
+}
+
+/// The simulated counterpart of a NIC opened with packet capture.
+pub struct Port {
+    shared: Arc<Shared>,
+    segment: SegmentId,
+    endpoint: usize,
+    rx: Mutex<mpsc::Receiver<Vec<u8>>>,
+    // applied on receipt, as the kernel would
+    filter: Mutex<Option<bpf::Program>>,
+    stop: AtomicBool,
+}
+
+impl link::Link for Port {
+    fn layer(&self) -> link::Layer {
+        link::Layer::Ethernet
+    }
+
+    fn send(&self, packet: &[u8]) -> Result<(), error::Error> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.transmit(self.segment, self.endpoint, packet.to_vec());
+        self.shared.wakeup.notify_all();
+        Ok(())
+    }
+
+    fn loop_infinite(&self, callback: &mut dyn FnMut(&[u8])) -> Result<(), error::Error> {
+        let rx = self.rx.lock().unwrap();
+
+        while !self.stop.swap(false, Ordering::SeqCst) {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(frame) => {
+                    let accepted = match *self.filter.lock().unwrap() {
+                        Some(ref program) => program.run(&frame) > 0,
+                        None => true,
+                    };
+                    if accepted {
+                        callback(&frame);
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn break_loop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    fn set_filter(&self, program: &bpf::Program) -> Result<(), error::Error> {
+        *self.filter.lock().unwrap() = Some(program.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        expectation::Options,
+        icmp,
+    };
+    use fixture::*;
+
+    /// Sends `count` frames from one port to another over a segment with
+    /// `conditions`, returning the number each carried in the order they
+    /// arrived in, and how long after sending they did.
+    fn send_numbered(conditions: Conditions, count: u8) -> Vec<(u8, Duration)> {
+        let net = Network::new(0);
+        let lan = net.segment(conditions);
+        let from = net.port(lan, mac(2), ip("10.0.0.2"));
+        let to = net.port(lan, mac(3), ip("10.0.0.3"));
+
+        let start = Instant::now();
+        for i in 0..count {
+            // the destination and source MACs, a local experimental EtherType and the number
+            let frame = [&mac(3).0[..], &mac(2).0[..], &[0x88, 0xB5, i][..]].concat();
+            link::Link::send(&from, &frame).unwrap();
+        }
+
+        let rx = to.rx.lock().unwrap();
+        let mut arrivals = Vec::new();
+        while let Ok(frame) = rx.recv_timeout(Duration::from_millis(300)) {
+            arrivals.push((frame[ETHERNET_HEADER_LEN], start.elapsed()));
+        }
+        arrivals
+    }
+
+    #[test]
+    fn latency_delays_frames_in_order() {
+        let latency = Duration::from_millis(50);
+        let arrivals = send_numbered(Conditions { latency, ..Default::default() }, 10);
+
+        let order: Vec<_> = arrivals.iter().map(|&(i, _)| i).collect();
+        assert_eq!(order, (0..10).collect::<Vec<_>>());
+        assert!(arrivals.iter().all(|&(_, at)| at >= latency));
+        assert!(arrivals[0].1 < latency * 4);
+    }
+
+    #[test]
+    fn jitter_reorders_frames() {
+        let (latency, jitter) = (Duration::from_millis(20), Duration::from_millis(100));
+        let arrivals = send_numbered(Conditions { latency, jitter, ..Default::default() }, 20);
+
+        let mut order: Vec<_> = arrivals.iter().map(|&(i, _)| i).collect();
+        assert_ne!(order, (0..20).collect::<Vec<_>>());
+        order.sort();
+        assert_eq!(order, (0..20).collect::<Vec<_>>());
+
+        assert!(arrivals.iter().all(|&(_, at)| at >= latency));
+        // the first and last arrivals are spread over most of the jitter
+        let spread = arrivals[19].1 - arrivals[0].1;
+        assert!(spread >= jitter / 2 && spread < jitter * 2, "{:?}", spread);
+    }
+
+    #[test]
+    fn ping_host_on_same_segment() {
+        let (_net, _lan, iface) = lan();
+
+        let reply = ping(&iface, ip("10.0.0.1"), 1).unwrap();
+        assert_eq!(reply.src, ip("10.0.0.1"));
+        assert_eq!(reply.ttl, 64);
+    }
+
+    #[test]
+    fn ping_through_router() {
+        let (_net, _wan, iface) = routed();
+
+        let reply = ping(&iface, ip("10.1.0.5"), 1).unwrap();
+        assert_eq!(reply.src, ip("10.1.0.5"));
+        assert_eq!(reply.ttl, 63);
+    }
+
+    #[test]
+    fn router_sends_time_exceeded() {
+        let (_net, _wan, port) = routed_port();
+
+        let echo = icmp::Echo {
+            identifier: 1,
+            sequence_number: 1,
+        };
+        let mut packet = ipv4::Packet::new(
+            ip("10.0.0.2"),
+            ip("10.1.0.5"),
+            ipv4::Payload::ICMP(icmp::Packet::echo_request(echo, "")),
+        );
+        packet.ttl = 1;
+        let frame = serialize(ethernet::Frame {
+            src: mac(2),
+            dst: mac(1),
+            ether_type: Some(ethernet::EtherType::IPv4),
+            payload: ethernet::Payload::IPv4(packet),
+        });
+        link::Link::send(&port, &frame).unwrap();
+
+        let reply = port.rx.lock().unwrap().recv_timeout(TIMEOUT).unwrap();
+        let (_, reply) = ethernet::Frame::parse(&reply).unwrap();
+        match reply.payload {
+            ethernet::Payload::IPv4(ref packet) => {
+                assert_eq!(packet.src, ip("10.0.0.1"));
+                match packet.payload {
+                    ipv4::Payload::ICMP(ref icmp) => assert!(matches!(
+                        icmp.typ,
+                        icmp::Type::TimeExceeded(icmp::TimeExceeded::TTLExpired)
+                    )),
+                    _ => panic!("expected ICMP"),
+                }
+            }
+            _ => panic!("expected IPv4"),
+        }
+    }
+
+    #[test]
+    fn lossy_segment_drops_frames() {
+        let net = Network::new(0);
+        let lan = net.segment(Conditions { loss: 1.0, ..Default::default() });
+        net.host(lan, Host::new(mac(1), ip("10.0.0.1")));
+        let port = net.port(lan, mac(2), ip("10.0.0.2"));
+
+        link::Link::send(&port, &arp_request(&nic("10.0.0.2", 2, "10.0.0.1"))).unwrap();
+        let rx = port.rx.lock().unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn duplicating_segment_delivers_twice() {
+        let net = Network::new(0);
+        let lan = net.segment(Conditions { duplication: 1.0, ..Default::default() });
+        net.host(lan, Host::new(mac(1), ip("10.0.0.1")));
+        let port = net.port(lan, mac(2), ip("10.0.0.2"));
+
+        link::Link::send(&port, &arp_request(&nic("10.0.0.2", 2, "10.0.0.1"))).unwrap();
+        let rx = port.rx.lock().unwrap();
+        // both copies of the request get answered, and both answers doubled
+        for _ in 0..4 {
+            assert!(rx.recv_timeout(TIMEOUT).is_ok());
+        }
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn frames_over_mtu_are_dropped() {
+        let (net, wan, iface) = routed();
+        net.set_conditions(wan, Conditions { mtu: 100, ..Default::default() });
+
+        let rx = iface
+            .expect_ipv4(ipv4::Protocol::ICMP, Options::once(), |packet| Some(packet.clone()))
+            .unwrap();
+        let echo = icmp::Echo {
+            identifier: 1,
+            sequence_number: 1,
+        };
+        let payload = ipv4::Payload::ICMP(icmp::Packet::echo_request(echo, vec![0u8; 200]));
+        iface.send_ipv4(payload, &ip("10.1.0.5")).unwrap();
+
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{
+    error,
+    expectation,
+    icmp,
+    ipv4,
+    pinger,
+    Interface,
+};
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    thread,
+    time,
+};
+
+/// A host that answered a sweep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Responder {
+    pub addr: ipv4::Addr,
+    #[cfg_attr(feature = "serde", serde(rename = "rtt_ms", serialize_with = "crate::json::millis"))]
+    pub rtt: time::Duration,
+}
+
+/// Sends one echo request to every host of a block, at a steady rate,
+/// and collects who answers. Build one with `Sweep::builder`.
+pub struct Sweep {
+    iface: Interface,
+    cidr: ipv4::Cidr,
+    rate: u32,
+    timeout: time::Duration,
+    identifier: u16,
+}
+
+pub struct SweepBuilder {
+    iface: Option<Interface>,
+    cidr: ipv4::Cidr,
+    rate: u32,
+    timeout: time::Duration,
+    identifier: u16,
+}
+
+impl Sweep {
+    pub fn builder(cidr: ipv4::Cidr) -> SweepBuilder {
+        SweepBuilder {
+            iface: None,
+            cidr,
+            rate: 100,
+            timeout: time::Duration::from_secs(1),
+            identifier: rand::random(),
+        }
+    }
+
+    pub fn cidr(&self) -> ipv4::Cidr {
+        self.cidr
+    }
+
+    /// Sweeps the whole block, returning who answered sorted by address.
+    /// Hosts the OS refuses to send to, like broadcast addresses, are
+    /// skipped; any other failure to send ends the sweep.
+    ///
+    /// Each request gets the next sequence number, so replies are matched
+    /// to the request they answer. Only the last 65536 requests are kept
+    /// track of, which is plenty as long as the timeout is shorter than
+    /// the time it takes to send that many.
+    pub fn run(&self) -> Result<Vec<Responder>, error::Error> {
+        let (identifier, cidr) = (self.identifier, self.cidr);
+        let rx = self.iface.expect_ipv4(ipv4::Protocol::ICMP, expectation::Options::forever(), move |packet| {
+            match packet.payload {
+                ipv4::Payload::ICMP(icmp::Packet { header: icmp::Header::EchoReply(ref echo), .. })
+                    if echo.identifier == identifier && cidr.contains(packet.src) =>
+                {
+                    Some((packet.src, echo.sequence_number, time::Instant::now()))
+                }
+                _ => None,
+            }
+        })?;
+
+        // where each sequence number went, and when
+        let mut sent = HashMap::new();
+        let mut responders = Vec::new();
+        let mut answered = HashSet::new();
+        let mut collect = |(addr, sequence_number, at): (ipv4::Addr, u16, time::Instant),
+                           sent: &HashMap<u16, (ipv4::Addr, time::Instant)>| {
+            if let Some(&(dest, sent_at)) = sent.get(&sequence_number) {
+                if dest == addr && answered.insert(addr) {
+                    responders.push(Responder {
+                        addr,
+                        rtt: at.saturating_duration_since(sent_at),
+                    });
+                }
+            }
+        };
+
+        let start = time::Instant::now();
+        let period = time::Duration::from_secs(1) / self.rate.max(1);
+        for (i, addr) in self.cidr.hosts().enumerate() {
+            let sequence_number = i as u16;
+            let echo = icmp::Echo {
+                identifier,
+                sequence_number,
+            };
+            let payload = ipv4::Payload::ICMP(icmp::Packet::echo_request(echo, "oppa sweep"));
+            sent.insert(sequence_number, (addr, time::Instant::now()));
+            match self.iface.send_ipv4(payload, &addr) {
+                Ok(()) => {}
+                Err(e) if is_broadcast_refusal(&e) => {}
+                Err(e) => return Err(e),
+            }
+
+            // replies are picked up while waiting for the next send
+            let next = start + period * (i as u32 + 1);
+            while let Ok(reply) = rx.recv_timeout(next.saturating_duration_since(time::Instant::now())) {
+                collect(reply, &sent);
+            }
+            thread::sleep(next.saturating_duration_since(time::Instant::now()));
+        }
+
+        let end = time::Instant::now() + self.timeout;
+        while let Ok(reply) = rx.recv_timeout(end.saturating_duration_since(time::Instant::now())) {
+            collect(reply, &sent);
+        }
+
+        responders.sort_by_key(|responder| responder.addr);
+        Ok(responders)
+    }
+}
+
+/// Whether the OS refused to send because the destination is a network
+/// or broadcast address, which is expected when sweeping a whole block.
+fn is_broadcast_refusal(e: &error::Error) -> bool {
+    match *e {
+        #[cfg(unix)]
+        error::Error::IO(ref e) => e.raw_os_error() == Some(libc::EACCES),
+        #[cfg(not(unix))]
+        error::Error::IO(ref e) => e.kind() == std::io::ErrorKind::PermissionDenied,
+        _ => false,
+    }
+}
+
+impl SweepBuilder {
+    /// Sweeps through `iface` rather than one opened by `build`.
+    pub fn interface(self, iface: Interface) -> Self {
+        Self {
+            iface: Some(iface),
+            ..self
+        }
+    }
+
+    /// Echo requests sent per second.
+    pub fn rate(self, rate: u32) -> Self {
+        Self { rate, ..self }
+    }
+
+    /// How long to wait for replies after the last request.
+    pub fn timeout(self, timeout: time::Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    pub fn identifier(self, identifier: u16) -> Self {
+        Self { identifier, ..self }
+    }
+
+    pub fn build(self) -> Result<Sweep, error::Error> {
+        let iface = match self.iface {
+            Some(iface) => iface,
+            None => pinger::default_interface()?,
+        };
+
+        Ok(Sweep {
+            iface,
+            cidr: self.cidr,
+            rate: self.rate,
+            timeout: self.timeout,
+            identifier: self.identifier,
+        })
+    }
+}
+
+/// How the hosts answering a sweep changed since a previous one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diff {
+    pub appeared: Vec<ipv4::Addr>,
+    pub disappeared: Vec<ipv4::Addr>,
+}
+
+impl Diff {
+    pub fn new(previous: &[ipv4::Addr], current: &[ipv4::Addr]) -> Self {
+        let (previous_set, current_set): (HashSet<_>, HashSet<_>) =
+            (previous.iter().collect(), current.iter().collect());
+        let mut diff = Self {
+            appeared: current.iter().filter(|addr| !previous_set.contains(addr)).copied().collect(),
+            disappeared: previous.iter().filter(|addr| !current_set.contains(addr)).copied().collect(),
+        };
+        diff.appeared.sort();
+        diff.disappeared.sort();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.appeared.is_empty() && self.disappeared.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ethernet,
+        link::Link,
+        sim::{
+            fixture::*,
+            Host,
+        },
+    };
+    use std::{
+        io,
+        time::Duration,
+    };
+
+    #[test]
+    fn diff_of_sweeps() {
+        let previous = [ip("10.0.0.3"), ip("10.0.0.1"), ip("10.0.0.2")];
+        let current = [ip("10.0.0.4"), ip("10.0.0.2"), ip("10.0.0.1")];
+
+        let diff = Diff::new(&previous, &current);
+        assert_eq!(diff.appeared, vec![ip("10.0.0.4")]);
+        assert_eq!(diff.disappeared, vec![ip("10.0.0.3")]);
+        assert!(Diff::new(&current, &current).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_refusals_to_broadcast_are_skipped() {
+        let refused = error::Error::IO(io::Error::from_raw_os_error(libc::EACCES));
+        let unreachable = error::Error::IO(io::Error::from_raw_os_error(libc::ENETUNREACH));
+        assert!(is_broadcast_refusal(&refused));
+        assert!(!is_broadcast_refusal(&unreachable));
+        assert!(!is_broadcast_refusal(&error::Error::Closed));
+    }
+
+    #[test]
+    fn sweep_lists_responders() {
+        let (net, wan, iface) = routed();
+        net.host(wan, Host::new(mac(104), ip("10.1.0.4")));
+        net.host(wan, Host { echo: false, ..Host::new(mac(106), ip("10.1.0.6")) });
+
+        let sweep = Sweep::builder("10.1.0.0/29".parse().unwrap())
+            .interface(iface)
+            .rate(1000)
+            .timeout(Duration::from_millis(300))
+            .build()
+            .unwrap();
+        let responders: Vec<_> = sweep.run().unwrap().into_iter().map(|responder| responder.addr).collect();
+        assert_eq!(responders, vec![ip("10.1.0.1"), ip("10.1.0.4"), ip("10.1.0.5")]);
+    }
+
+    #[test]
+    fn sweep_ignores_replies_to_other_requests() {
+        let (net, lan, iface) = lan();
+        // .3 isn't there, but something keeps answering .1's request in its name
+        let forger = net.port(lan, mac(3), ip("10.0.0.3"));
+        let echo = icmp::Echo {
+            identifier: 0x5EE9,
+            sequence_number: 0,
+        };
+        let reply = ipv4::Packet::new(
+            ip("10.0.0.3"),
+            ip("10.0.0.2"),
+            ipv4::Payload::ICMP(icmp::Packet::echo_reply(echo, "oppa sweep")),
+        );
+        let frame = serialize(ethernet::Frame {
+            src: mac(3),
+            dst: mac(2),
+            ether_type: Some(ethernet::EtherType::IPv4),
+            payload: ethernet::Payload::IPv4(reply),
+        });
+        let forging = std::thread::spawn(move || {
+            for _ in 0..40 {
+                forger.send(&frame).unwrap();
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        let sweep = Sweep::builder("10.0.0.0/29".parse().unwrap())
+            .interface(iface)
+            .rate(20)
+            .timeout(Duration::from_millis(100))
+            .identifier(0x5EE9)
+            .build()
+            .unwrap();
+        let responders: Vec<_> = sweep.run().unwrap().into_iter().map(|responder| responder.addr).collect();
+        forging.join().unwrap();
+        assert_eq!(responders, vec![ip("10.0.0.1")]);
+    }
+}